@@ -12,15 +12,75 @@
 
 use crate::prelude::*;
 use afbv4::prelude::*;
+use libauth::prelude::*;
+use std::time::Duration;
 use typesv4::prelude::*;
 
 pub struct BindingCfg {
-    pub nfc_api: &'static str,
+    pub scard: ScardVerbs,
+    pub card_identify: ScardIdentify,
+    pub card_reads: Vec<ScardRead>,
+    pub legacy_contract: bool,
+    pub admin_permission: &'static str,
+    pub ocpp_permission: &'static str,
     pub ocpp_api: Option<&'static str>,
     pub engy_api: &'static str,
+    pub backends: Vec<&'static str>,
+    pub local_list: Option<LocalAuthList>,
+    pub uid_registry: Option<UidRegistry>,
+    pub cache: AuthCache,
+    pub lockout: AuthLockout,
+    pub policy: AuthPolicy,
     pub tic: u32,
 }
 
+// scard get/set verb pairs, set verbs are only used by provisioning
+fn scard_sectors(jsectors: &JsoncObj) -> Result<Vec<ScardSector>, AfbError> {
+    let mut sectors = Vec::new();
+    for idx in 0..jsectors.count()? {
+        let jsector = jsectors.index::<JsoncObj>(idx)?;
+        sectors.push(ScardSector {
+            get: jsector.get::<&'static str>("get")?,
+            set: jsector.default::<&'static str>("set", "")?,
+        });
+    }
+    if sectors.is_empty() {
+        return afb_error!("auth-config-fail", "card step requires at least one sector");
+    }
+    Ok(sectors)
+}
+
+// card step verbs from {'get':'xxx','set':'xxx','required':true,'on_error':'retry|refuse'}
+fn scard_step(
+    jsteps: &Option<JsoncObj>,
+    step: &str,
+    get: &'static str,
+    set: &'static str,
+    required: bool,
+) -> Result<ScardStepCfg, AfbError> {
+    let jstep = match jsteps {
+        Some(jsteps) => jsteps.optional::<JsoncObj>(step)?,
+        None => None,
+    };
+    let jstep = match jstep {
+        Some(jstep) => jstep,
+        None => return Ok(ScardStepCfg::new(get, set, required)),
+    };
+
+    let sectors = match jstep.optional::<JsoncObj>("sectors")? {
+        Some(jsectors) => scard_sectors(&jsectors)?,
+        None => vec![ScardSector {
+            get: jstep.default::<&'static str>("get", get)?,
+            set: jstep.default::<&'static str>("set", set)?,
+        }],
+    };
+    Ok(ScardStepCfg {
+        sectors,
+        required: jstep.default::<bool>("required", required)?,
+        on_error: ScardOnError::parse(jstep.default::<&str>("on_error", "retry")?)?,
+    })
+}
+
 // Binding init callback started at binding load time before any API exist
 // -----------------------------------------
 pub fn binding_init(rootv4: AfbApiV4, jconf: JsoncObj) -> Result<&'static AfbApi, AfbError> {
@@ -28,6 +88,7 @@ pub fn binding_init(rootv4: AfbApiV4, jconf: JsoncObj) -> Result<&'static AfbApi
 
     // add binding custom converter
     auth_registers()?;
+    contract_registers()?;
     ocpp_registers()?;
     engy_registers()?;
     chmgr_registers()?;
@@ -40,10 +101,367 @@ pub fn binding_init(rootv4: AfbApiV4, jconf: JsoncObj) -> Result<&'static AfbApi
     let engy_api = jconf.default::<&'static str>("engy_api", "engy")?;
     let tic = jconf.default::<u32>("tic", 0)?;
 
+    // authentication backends chain, default nfc+ocpp when ocpp is available
+    let backends = match jconf.optional::<JsoncObj>("backends")? {
+        Some(jbackends) => {
+            let mut backends = Vec::new();
+            for idx in 0..jbackends.count()? {
+                backends.push(jbackends.index::<&'static str>(idx)?);
+            }
+            backends
+        }
+        None => {
+            if ocpp_api.is_some() {
+                vec!["nfc", "ocpp"]
+            } else {
+                vec!["nfc"]
+            }
+        }
+    };
+
+    // OCPP local authorization list, disabled when not configured
+    let local_list = match jconf.optional::<JsoncObj>("local_list")? {
+        Some(jlist) => {
+            let path = jlist.optional::<&'static str>("path")?;
+            let pre_authorize = jlist.default::<bool>("pre_authorize", true)?;
+            Some(LocalAuthList::new(path, pre_authorize)?)
+        }
+        None => None,
+    };
+
+    // card hardware uid to tagid binding, disabled when not configured
+    let uid_registry = match jconf.optional::<JsoncObj>("uid_binding")? {
+        Some(jbinding) => {
+            let path = jbinding.optional::<&'static str>("registry")?;
+            let required = jbinding.default::<bool>("required", false)?;
+            Some(UidRegistry::new(path, required)?)
+        }
+        None => None,
+    };
+
+    // OCPP AuthorizationCacheEnabled policy
+    let cache = match jconf.optional::<JsoncObj>("authorization_cache")? {
+        Some(jcache) => {
+            let enabled = jcache.default::<bool>("enabled", true)?;
+            let size = jcache.default::<u32>("size", 64)?;
+            let max_age = jcache.default::<u32>("max_age", 86400)?;
+            AuthCache::new(enabled, size as usize, Duration::from_secs(max_age as u64))
+        }
+        None => AuthCache::new(false, 0, Duration::from_secs(0)),
+    };
+
+    // failed login lockout, thresholds within sliding window (seconds), 0 disables the scope
+    let lockout = match jconf.optional::<JsoncObj>("lockout")? {
+        Some(jlockout) => AuthLockout::new(
+            Duration::from_secs(jlockout.default::<u32>("window", 300)? as u64),
+            jlockout.default::<u32>("tag_threshold", 5)?,
+            jlockout.default::<u32>("global_threshold", 20)?,
+            Duration::from_secs(jlockout.default::<u32>("base", 30)? as u64),
+            Duration::from_secs(jlockout.default::<u32>("max", 3600)? as u64),
+        ),
+        None => AuthLockout::new(Duration::ZERO, 0, 0, Duration::ZERO, Duration::ZERO),
+    };
+
+    // offline behavior when ocpp is unreachable, default refuse. Energy budget cutoff
+    // is pushed to energy manager, it requires energy_limits enabled
+    let offline = match jconf.optional::<JsoncObj>("offline")? {
+        Some(joffline) => OfflinePolicy {
+            local_authorize: joffline.default::<bool>("local_authorize", true)?,
+            allow_unknown: joffline.default::<bool>("allow_unknown", false)?,
+            unknown_emax: joffline.default::<u32>("unknown_emax", 0)?,
+        },
+        None => OfflinePolicy {
+            local_authorize: false,
+            allow_unknown: false,
+            unknown_emax: 0,
+        },
+    };
+
+    // present badge to stop, masters badges may stop any session
+    let mut stop = match jconf.optional::<JsoncObj>("stop")? {
+        Some(jstop) => {
+            let mut masters = Vec::new();
+            if let Some(jmasters) = jstop.optional::<JsoncObj>("masters")? {
+                for idx in 0..jmasters.count()? {
+                    masters.push(jmasters.index::<String>(idx)?);
+                }
+            }
+            StopPolicy {
+                badge: jstop.default::<bool>("badge", true)?,
+                masters,
+            }
+        }
+        None => StopPolicy {
+            badge: false,
+            masters: Vec::new(),
+        },
+    };
+
+    // card presentation timeout in ms, 0 to read card only once
+    let login = LoginPolicy {
+        timeout: Duration::from_millis(jconf.default::<u32>("login_timeout", 0)? as u64),
+        poll: Duration::from_millis(jconf.default::<u32>("login_poll", 250)? as u64),
+    };
+
+    // badge contract signature keys, hex encoded hmac secret or ed25519 public key
+    let signature = match jconf.optional::<JsoncObj>("signature")? {
+        Some(jsignature) => {
+            let mut keys = Vec::new();
+            if let Some(jkeys) = jsignature.optional::<JsoncObj>("keys")? {
+                for idx in 0..jkeys.count()? {
+                    let jkey = jkeys.index::<JsoncObj>(idx)?;
+                    let key = ContractKey::new(
+                        jkey.get::<&str>("uid")?,
+                        jkey.default::<&str>("type", "hmac-sha256")?,
+                        jkey.get::<&str>("value")?,
+                    )?;
+                    // ed25519 private seed only on provisioning stations
+                    let key = match jkey.optional::<&str>("seed")? {
+                        Some(seed) => key.set_seed(seed)?,
+                        None => key,
+                    };
+                    keys.push(key);
+                }
+            }
+            SignaturePolicy {
+                required: jsignature.default::<bool>("required", true)?,
+                keys,
+                sign_key: jsignature.optional::<String>("sign_key")?,
+            }
+        }
+        None => SignaturePolicy {
+            required: false,
+            keys: Vec::new(),
+            sign_key: None,
+        },
+    };
+
+    // logical card steps mapped on scard verbs, default scard-binding-rs layout
+    let jsteps = jconf.optional::<JsoncObj>("card_steps")?;
+    let mut contract = scard_step(&jsteps, "contract", "get-contract", "set-contract", true)?;
+    // legacy contract_sectors layout
+    if let Some(jsectors) = jconf.optional::<JsoncObj>("contract_sectors")? {
+        contract.sectors = scard_sectors(&jsectors)?;
+    }
+    let scard = ScardVerbs {
+        api: nfc_api,
+        tag: scard_step(&jsteps, "tag", "get-tagid", "set-tagid", true)?,
+        contract,
+        uid: scard_step(&jsteps, "uid", "get-uuid", "", true)?,
+        name: scard_step(&jsteps, "name", "get-name", "set-name", false)?,
+    };
+    if !scard.tag.required {
+        return afb_error!("auth-config-fail", "card_steps tag step cannot be optional");
+    }
+    // uid_binding enables provisioning, every written sector needs its set verb
+    if uid_registry.is_some() {
+        for (step, cfg) in [
+            ("tag", &scard.tag),
+            ("contract", &scard.contract),
+            ("name", &scard.name),
+        ] {
+            if cfg.sectors.iter().any(|sector| sector.set.is_empty()) {
+                return afb_error!(
+                    "auth-config-fail",
+                    "card_steps {} sectors require a set verb for provisioning",
+                    step
+                );
+            }
+        }
+    }
+
+    // session limits pushed to energy manager, site limits restored at logout.
+    // Enabled by default, "enabled":false leaves limits to energy manager
+    let energy = match jconf.optional::<JsoncObj>("energy_limits")? {
+        Some(jlimits) => EnergyPolicy {
+            api: jlimits
+                .default::<bool>("enabled", true)?
+                .then_some(engy_api),
+            verb: jlimits.default::<&'static str>("verb", "config")?,
+        },
+        None => EnergyPolicy {
+            api: Some(engy_api),
+            verb: "config",
+        },
+    };
+    if offline.allow_unknown && offline.unknown_emax > 0 && energy.api.is_none() {
+        return afb_error!(
+            "auth-config-fail",
+            "offline unknown_emax budget requires energy_limits enabled"
+        );
+    }
+
+    // site limits, OCPP policy and card reads after tagid, default 32A/22kW+ocpp+contract
+    let mut card_reads = Vec::new();
+    let mut card_identify = ScardIdentify::Tagid;
+    let mut legacy_contract = false;
+    let defaults = match jconf.optional::<JsoncObj>("defaults")? {
+        Some(jdefaults) => {
+            // cards without data sector, uid_order lsb when reader returns uid LSB first
+            card_identify = match jdefaults.default::<&str>("identify", "tagid")? {
+                "tagid" => ScardIdentify::Tagid,
+                "uid" => ScardIdentify::Uid {
+                    reverse: match jdefaults.default::<&str>("uid_order", "msb")? {
+                        "msb" => false,
+                        "lsb" => true,
+                        order => {
+                            return afb_error!(
+                                "auth-config-fail",
+                                "defaults uid_order:{} should be msb|lsb",
+                                order
+                            )
+                        }
+                    },
+                },
+                identify => {
+                    return afb_error!(
+                        "auth-config-fail",
+                        "defaults identify:{} should be tagid|uid",
+                        identify
+                    )
+                }
+            };
+            // JSON contracts written before versioning, missing limits come from defaults
+            legacy_contract = jdefaults.default::<bool>("legacy_contract", false)?;
+            match jdefaults.optional::<JsoncObj>("card")? {
+                Some(jcard) => {
+                    for idx in 0..jcard.count()? {
+                        card_reads.push(ScardRead::parse(jcard.index::<&str>(idx)?)?);
+                    }
+                }
+                None => card_reads.push(ScardRead::Contract),
+            }
+            DefaultPolicy {
+                imax: jdefaults.default::<u32>("imax", 32)?,
+                pmax: jdefaults.default::<u32>("pmax", 22)?,
+                ocpp_check: jdefaults.default::<bool>("ocpp_check", true)?,
+            }
+        }
+        None => {
+            card_reads.push(ScardRead::Contract);
+            DefaultPolicy {
+                imax: 32,
+                pmax: 22,
+                ocpp_check: true,
+            }
+        }
+    };
+
+    let admin_permission = jconf.default::<&'static str>("admin_permission", "acl:auth:admin")?;
+    // local list update verbs, granted to ocpp binding
+    let ocpp_permission = jconf.default::<&'static str>("ocpp_permission", admin_permission)?;
+
+    // presented tag normalization and accepted formats, default only strips padding
+    let mut case_set = false;
+    let mut idtag = match jconf.optional::<JsoncObj>("idtag")? {
+        Some(jidtag) => {
+            let mut formats = Vec::new();
+            if let Some(jformats) = jidtag.optional::<JsoncObj>("formats")? {
+                for idx in 0..jformats.count()? {
+                    formats.push(IdTagFormat::parse(jformats.index::<&str>(idx)?)?);
+                }
+            }
+            case_set = jidtag.optional::<&str>("case")?.is_some();
+            IdTagPolicy {
+                case: IdTagCase::parse(jidtag.default::<&str>("case", "keep")?)?,
+                hex: jidtag.default::<bool>("hex", false)?,
+                formats,
+            }
+        }
+        None => IdTagPolicy {
+            case: IdTagCase::Keep,
+            hex: false,
+            formats: Vec::new(),
+        },
+    };
+
+    // uid only cards have no contract to verify, their idTag is always uppercase hex
+    if let ScardIdentify::Uid { .. } = card_identify {
+        if signature.required && signature.is_enabled() {
+            return afb_error!(
+                "auth-config-fail",
+                "defaults identify:uid cards have no contract, signature cannot be required"
+            );
+        }
+        if case_set && idtag.case != IdTagCase::Upper {
+            return afb_error!(
+                "auth-config-fail",
+                "defaults identify:uid cards require idtag case:upper"
+            );
+        }
+        idtag.case = IdTagCase::Upper;
+    }
+
+    // master badges are compared with normalized presented tags
+    for master in stop.masters.iter_mut() {
+        *master = idtag.normalize(master)?;
+    }
+
+    // free-vend/autostart sites, login is authorized without badge under configured idTag
+    let free_vend = match jconf.optional::<JsoncObj>("free_vend")? {
+        Some(jfree_vend) => FreeVendPolicy {
+            idtag: match jfree_vend.default::<bool>("enabled", true)? {
+                true => Some(idtag.normalize(jfree_vend.get::<&str>("idtag")?)?),
+                false => None,
+            },
+        },
+        None => FreeVendPolicy { idtag: None },
+    };
+    // free-vend sessions are only accounted through ocpp transactions
+    if free_vend.idtag.is_some() && (ocpp_api.is_none() || !backends.contains(&"ocpp")) {
+        return afb_error!(
+            "auth-config-fail",
+            "free_vend requires ocpp_api and ocpp backend"
+        );
+    }
+
+    // card holder name and tagid pseudonymisation on logs and public events
+    let privacy = match jconf.optional::<JsoncObj>("privacy")? {
+        Some(jprivacy) => PrivacyPolicy {
+            name: PrivacyMask::parse(jprivacy.default::<&str>("name", "initials")?)?,
+            tagid: PrivacyMask::parse(jprivacy.default::<&str>("tagid", "clear")?)?,
+            salt: jprivacy.default::<String>("salt", String::new())?,
+        },
+        None => PrivacyPolicy {
+            name: PrivacyMask::Initials,
+            tagid: PrivacyMask::Clear,
+            salt: String::new(),
+        },
+    };
+    // an unsalted hash of a short tagid is reversed by brute force
+    let hashed = privacy.name == PrivacyMask::Hash || privacy.tagid == PrivacyMask::Hash;
+    if hashed && privacy.salt.is_empty() {
+        return afb_error!(
+            "auth-config-fail",
+            "privacy hash mask requires a salt unique per charger"
+        );
+    }
+
     let config = BindingCfg {
-        nfc_api,
+        scard,
+        card_identify,
+        card_reads,
+        legacy_contract,
+        admin_permission,
+        ocpp_permission,
         ocpp_api,
         engy_api,
+        backends,
+        local_list,
+        uid_registry,
+        cache,
+        lockout,
+        policy: AuthPolicy {
+            defaults,
+            offline,
+            stop,
+            login,
+            signature,
+            privacy,
+            energy,
+            idtag,
+            free_vend,
+        },
         tic,
     };
 
@@ -28,6 +28,78 @@ fn engy_event_cb(evt: &AfbEventMsg, args: &AfbRqtData, ctx: &AfbCtxData) -> Resu
     Ok(())
 }
 
+struct OcppEvtCtx {
+    mgr: &'static ManagerHandle,
+}
+// smart charging limits from ocpp binding
+fn ocpp_event_cb(evt: &AfbEventMsg, args: &AfbRqtData, ctx: &AfbCtxData) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<OcppEvtCtx>()?;
+    if let OcppMsg::PowerLimit(power_limit) = args.get::<&OcppMsg>(0)? {
+        afb_log_msg!(Debug, evt, "ocpp power limit:{:?}", power_limit);
+        ctx.mgr.ocpp_power_limit(power_limit)?;
+    }
+    Ok(())
+}
+
+// ocpp/energy bindings may start after auth, subscriptions are retried until they answer (ms)
+const EVT_SUBSCRIBE_DELAY: u32 = 1000;
+const EVT_SUBSCRIBE_RETRY: u32 = 10000;
+
+type EvtSubscribeFn = fn(AfbApiV4, &'static str) -> Result<AfbRqtData, AfbError>;
+
+// smart charging limits are site wide
+fn ocpp_subscribe(apiv4: AfbApiV4, ocpp_api: &'static str) -> Result<AfbRqtData, AfbError> {
+    AfbSubCall::call_sync(apiv4, ocpp_api, "subscribe", true)
+}
+
+// energy state drives session budget even when ocpp is down or not required
+fn engy_subscribe(apiv4: AfbApiV4, engy_api: &'static str) -> Result<AfbRqtData, AfbError> {
+    AfbSubCall::call_sync(apiv4, engy_api, "state", EnergyAction::SUBSCRIBE)
+}
+
+struct EvtSubscribeCtx {
+    api: &'static str,
+    subscribe: EvtSubscribeFn,
+    evt: &'static AfbEvent,
+}
+// subscribe once for binding lifetime
+fn evt_subscribe_cb(_timer: &AfbTimer, _decount: u32, ctx: &AfbCtxData) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<EvtSubscribeCtx>()?;
+    match (ctx.subscribe)(ctx.evt.get_apiv4(), ctx.api) {
+        Ok(_) => afb_log_msg!(Notice, ctx.evt, "api:{} events subscribed", ctx.api),
+        Err(error) => {
+            afb_log_msg!(
+                Warning,
+                ctx.evt,
+                "api:{} event subscription fail: {}",
+                ctx.api,
+                error
+            );
+            evt_subscribe(ctx.api, ctx.subscribe, ctx.evt, EVT_SUBSCRIBE_RETRY)?;
+        }
+    }
+    Ok(())
+}
+
+fn evt_subscribe(
+    api: &'static str,
+    subscribe: EvtSubscribeFn,
+    evt: &'static AfbEvent,
+    delay: u32,
+) -> Result<(), AfbError> {
+    AfbTimer::new("evt-subscribe")
+        .set_period(delay)
+        .set_decount(1)
+        .set_callback(evt_subscribe_cb)
+        .set_context(EvtSubscribeCtx {
+            api,
+            subscribe,
+            evt,
+        })
+        .start()?;
+    Ok(())
+}
+
 struct TimerCtx {
     mgr: &'static ManagerHandle,
     evt: &'static AfbEvent,
@@ -36,8 +108,7 @@ struct TimerCtx {
 // AfbTimerRegister!(TimerCtrl, timer_callback, TimerCtx);
 fn timer_callback(_timer: &AfbTimer, _decount: u32, ctx: &AfbCtxData) -> Result<(), AfbError> {
     let ctx = ctx.get_ref::<TimerCtx>()?;
-    let state = ctx.mgr.get_state()?;
-    ctx.evt.push(state.clone());
+    ctx.evt.push(ctx.mgr.get_public_state()?);
     Ok(())
 }
 
@@ -48,8 +119,8 @@ struct LoginRqtCtx {
 fn auth_rqt_cb(rqt: &AfbRequest, _args: &AfbRqtData, ctx: &AfbCtxData) -> Result<(), AfbError> {
     let ctx = ctx.get_ref::<LoginRqtCtx>()?;
     afb_log_msg!(Debug, rqt, "authentication login request");
-    let contract = ctx.mgr.login()?;
-    rqt.reply(contract, 0);
+    // reply is sent by manager once the asynchronous login flow completes
+    ctx.mgr.login(rqt)?;
     Ok(())
 }
 
@@ -64,12 +135,166 @@ fn logout_auth_cb(
 ) -> Result<(), AfbError> {
     let ctx = ctx.get_ref::<LogoutRqtCtx>()?;
     afb_log_msg!(Debug, rqt, "authentication logout request");
-    let energy_session= args.get::<i32>(0)?;
+    // optional transaction id overload, only a missing or null argument defaults to
+    // session transaction, anything else should be a valid transaction id
+    let energy_session = match args.get::<JsoncObj>(0) {
+        Err(_) => None,
+        Ok(jarg) => match jarg.to_string().trim() {
+            "null" => None,
+            value => match value.parse::<i32>() {
+                Ok(tid) => Some(tid),
+                Err(_) => {
+                    return afb_error!(
+                        "auth-logout-arg",
+                        "invalid transaction id:{} expected integer",
+                        value
+                    )
+                }
+            },
+        },
+    };
     let contract = ctx.mgr.logout(energy_session)?;
     rqt.reply(contract, 0);
     Ok(())
 }
 
+struct LocalListRqtCtx {
+    mgr: &'static ManagerHandle,
+}
+// SendLocalList request from ocpp binding
+fn send_local_list_cb(
+    rqt: &AfbRequest,
+    args: &AfbRqtData,
+    ctx: &AfbCtxData,
+) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<LocalListRqtCtx>()?;
+    let request = args.get::<&OcppLocalList>(0)?;
+    let status = ctx.mgr.send_local_list(request)?;
+    rqt.reply(status, 0);
+    Ok(())
+}
+
+// GetLocalListVersion request from ocpp binding
+fn get_local_list_version_cb(
+    rqt: &AfbRequest,
+    _args: &AfbRqtData,
+    ctx: &AfbCtxData,
+) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<LocalListRqtCtx>()?;
+    rqt.reply(ctx.mgr.get_local_list_version(), 0);
+    Ok(())
+}
+
+// authentication state machine transition table for HMI developers
+fn describe_cb(rqt: &AfbRequest, _args: &AfbRqtData, _ctx: &AfbCtxData) -> Result<(), AfbError> {
+    rqt.reply(auth_describe(), 0);
+    Ok(())
+}
+
+struct ProvisionRqtCtx {
+    mgr: &'static ManagerHandle,
+    scard: ScardVerbs,
+}
+// admin only badge provisioning, card should stay on reader until reply
+fn issue_badge_cb(rqt: &AfbRequest, args: &AfbRqtData, ctx: &AfbCtxData) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<ProvisionRqtCtx>()?;
+    let request = args.get::<&AuthBadgeRequest>(0)?;
+    let badge = ctx.mgr.issue_badge(&ctx.scard, request)?;
+    rqt.reply(badge, 0);
+    Ok(())
+}
+
+fn update_contract_cb(
+    rqt: &AfbRequest,
+    args: &AfbRqtData,
+    ctx: &AfbCtxData,
+) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<ProvisionRqtCtx>()?;
+    let contract = args.get::<&Contract>(0)?;
+    let badge = ctx.mgr.update_contract(&ctx.scard, contract)?;
+    rqt.reply(badge, 0);
+    Ok(())
+}
+
+fn revoke_badge_cb(rqt: &AfbRequest, args: &AfbRqtData, ctx: &AfbCtxData) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<ProvisionRqtCtx>()?;
+    let tagid = args.get::<String>(0)?;
+    let badge = ctx.mgr.revoke_badge(&tagid)?;
+    rqt.reply(badge, 0);
+    Ok(())
+}
+
+struct SessionRqtCtx {
+    mgr: &'static ManagerHandle,
+}
+// admin only session state, personal data is not masked
+fn session_request_cb(
+    rqt: &AfbRequest,
+    _args: &AfbRqtData,
+    ctx: &AfbCtxData,
+) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<SessionRqtCtx>()?;
+    let data_set = ctx.mgr.get_state()?;
+    rqt.reply(data_set.clone(), 0);
+    Ok(())
+}
+
+struct CacheRqtCtx {
+    mgr: &'static ManagerHandle,
+}
+// admin only, dump or clear authorization cache
+fn cache_request_cb(
+    rqt: &AfbRequest,
+    args: &AfbRqtData,
+    ctx: &AfbCtxData,
+) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<CacheRqtCtx>()?;
+    match args.get::<&AuthCacheAction>(0)? {
+        AuthCacheAction::DUMP => {
+            rqt.reply(ctx.mgr.cache_dump(), 0);
+        }
+        AuthCacheAction::CLEAR => {
+            ctx.mgr.cache_clear();
+            rqt.reply(AFB_NO_DATA, 0);
+        }
+    }
+    Ok(())
+}
+
+struct LockoutRqtCtx {
+    mgr: &'static ManagerHandle,
+}
+// dump or reset failed login counters
+fn lockout_request_cb(
+    rqt: &AfbRequest,
+    args: &AfbRqtData,
+    ctx: &AfbCtxData,
+) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<LockoutRqtCtx>()?;
+    match args.get::<&AuthLockoutAction>(0)? {
+        AuthLockoutAction::READ => {
+            rqt.reply(ctx.mgr.lockout_dump(), 0);
+        }
+        AuthLockoutAction::RESET => {
+            ctx.mgr.lockout_reset();
+            rqt.reply(AFB_NO_DATA, 0);
+        }
+    }
+    Ok(())
+}
+
+struct CancelRqtCtx {
+    mgr: &'static ManagerHandle,
+}
+// abort pending login request
+fn cancel_rqt_cb(rqt: &AfbRequest, _args: &AfbRqtData, ctx: &AfbCtxData) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<CancelRqtCtx>()?;
+    afb_log_msg!(Debug, rqt, "authentication cancel request");
+    ctx.mgr.cancel()?;
+    rqt.reply(AFB_NO_DATA, 0);
+    Ok(())
+}
+
 struct SubscribeData {
     event: &'static AfbEvent,
 }
@@ -103,8 +328,7 @@ fn state_request_cb(
     let ctx = ctx.get_ref::<StateRequestCtx>()?;
     match args.get::<&AuthAction>(0)? {
         AuthAction::READ => {
-            let data_set = ctx.mgr.get_state()?;
-            rqt.reply(data_set.clone(), 0);
+            rqt.reply(ctx.mgr.get_public_state()?, 0);
         }
 
         AuthAction::SUBSCRIBE => {
@@ -122,17 +346,85 @@ fn state_request_cb(
     Ok(())
 }
 
+// build authentication backend chain from binding config
+fn new_backend(uid: &str, config: &BindingCfg) -> Result<Box<dyn AuthBackend>, AfbError> {
+    let backend: Box<dyn AuthBackend> = match uid {
+        "nfc" => {
+            // card uid is part of signed contract payload and uid binding
+            let mut reads = config.card_reads.clone();
+            if (config.policy.signature.is_enabled() || config.uid_registry.is_some())
+                && !reads.contains(&ScardRead::Uid)
+            {
+                reads.push(ScardRead::Uid);
+            }
+            // opt-in, incomplete legacy JSON contracts are otherwise refused
+            let legacy = config.legacy_contract.then(|| Contract {
+                version: 1,
+                imax: config.policy.defaults.imax,
+                pmax: config.policy.defaults.pmax,
+                ocpp: config.policy.defaults.ocpp_check,
+                expiry_date: None,
+                issuer: None,
+                emax: 0,
+                windows: Vec::new(),
+                kid: None,
+                signature: None,
+            });
+            ScardBackend::new(config.scard.clone(), config.card_identify, &reads, legacy)
+        }
+        "ocpp" => match config.ocpp_api {
+            Some(ocpp_api) => OcppBackend::new(ocpp_api),
+            None => return afb_error!("auth-backend-config", "backend:ocpp requires ocpp_api"),
+        },
+        _ => return afb_error!("auth-backend-config", "unknown backend:{}", uid),
+    };
+    Ok(backend)
+}
+
 pub(crate) fn register_verbs(api: &mut AfbApi, config: BindingCfg) -> Result<(), AfbError> {
     let event = AfbEvent::new("msg");
-    let mgr = ManagerHandle::new(event, config.nfc_api, config.ocpp_api, config.engy_api);
+
+    let mut backends = Vec::new();
+    for uid in &config.backends {
+        backends.push(new_backend(uid, &config)?);
+    }
+    let state_event = AfbEvent::new("state");
+    let security_event = AfbEvent::new("security");
+    let lockout_event = AfbEvent::new("lockout");
+    let limit_event = AfbEvent::new("limit");
+    let mgr = ManagerHandle::new(
+        AuthEvents {
+            msg: event,
+            state: state_event,
+            security: security_event,
+            lockout: lockout_event,
+            limit: limit_event,
+        },
+        backends,
+        config.local_list,
+        config.uid_registry,
+        config.cache,
+        config.lockout,
+        config.policy,
+    );
 
     let engy_handler = AfbEvtHandler::new("energy-evt")
         .set_pattern(to_static_str(format!("{}/*", config.engy_api)))
         .set_callback(engy_event_cb)
         .set_context(EngyEvtCtx { mgr })
         .finalize()?;
+    evt_subscribe(config.engy_api, engy_subscribe, event, EVT_SUBSCRIBE_DELAY)?;
+
+    if let Some(ocpp_api) = config.ocpp_api {
+        let ocpp_handler = AfbEvtHandler::new("ocpp-evt")
+            .set_pattern(to_static_str(format!("{}/*", ocpp_api)))
+            .set_callback(ocpp_event_cb)
+            .set_context(OcppEvtCtx { mgr })
+            .finalize()?;
+        api.add_evt_handler(ocpp_handler);
+        evt_subscribe(ocpp_api, ocpp_subscribe, event, EVT_SUBSCRIBE_DELAY)?;
+    }
 
-    let state_event = AfbEvent::new("state");
     if config.tic > 0 {
         AfbTimer::new("tic-timer")
             .set_period(config.tic)
@@ -157,6 +449,14 @@ pub(crate) fn register_verbs(api: &mut AfbApi, config: BindingCfg) -> Result<(),
         .set_callback(logout_auth_cb)
         .set_context(LogoutRqtCtx { mgr })
         .set_info("Logout authenticate")
+        .set_usage("[transaction-id]")
+        .finalize()?;
+
+    let auth_cancel = AfbVerb::new("cancel authentication")
+        .set_name("cancel")
+        .set_callback(cancel_rqt_cb)
+        .set_context(CancelRqtCtx { mgr })
+        .set_info("Cancel pending login")
         .finalize()?;
 
     let state_verb = AfbVerb::new("auth-state")
@@ -170,6 +470,32 @@ pub(crate) fn register_verbs(api: &mut AfbApi, config: BindingCfg) -> Result<(),
         })
         .finalize()?;
 
+    // local list may mark any idTag accepted, only ocpp binding or admin may push it
+    let ocpp = AfbPermission::new(config.ocpp_permission);
+    let send_local_list = AfbVerb::new("send-local-list")
+        .set_name("send-local-list")
+        .set_callback(send_local_list_cb)
+        .set_context(LocalListRqtCtx { mgr })
+        .set_permission(ocpp)
+        .set_info("OCPP SendLocalList full/differential update")
+        .set_usage("{'version':1,'update':'full','list':[...]}")
+        .finalize()?;
+
+    let get_local_list_version = AfbVerb::new("get-local-list-version")
+        .set_name("get-local-list-version")
+        .set_callback(get_local_list_version_cb)
+        .set_context(LocalListRqtCtx { mgr })
+        .set_permission(ocpp)
+        .set_info("OCPP GetLocalListVersion (-1 when not enabled)")
+        .finalize()?;
+
+
+    let describe = AfbVerb::new("describe")
+        .set_name("describe")
+        .set_callback(describe_cb)
+        .set_info("authentication state machine transitions")
+        .finalize()?;
+
     let subscribe = AfbVerb::new("subscribe")
         .set_callback(subscribe_callback)
         .set_context(SubscribeData { event })
@@ -177,12 +503,117 @@ pub(crate) fn register_verbs(api: &mut AfbApi, config: BindingCfg) -> Result<(),
         .set_usage("true|false")
         .finalize()?;
 
+    let admin = AfbPermission::new(config.admin_permission);
+    let issue_badge = AfbVerb::new("issue-badge")
+        .set_name("issue-badge")
+        .set_callback(issue_badge_cb)
+        .set_context(ProvisionRqtCtx {
+            mgr,
+            scard: config.scard.clone(),
+        })
+        .set_permission(admin)
+        .set_info("write tagid, name and signed contract on presented card")
+        .set_usage("{'tagid':'xxx','name':'xxx','contract':{...}}")
+        .finalize()?;
+
+    let update_contract = AfbVerb::new("update-contract")
+        .set_name("update-contract")
+        .set_callback(update_contract_cb)
+        .set_context(ProvisionRqtCtx {
+            mgr,
+            scard: config.scard.clone(),
+        })
+        .set_permission(admin)
+        .set_info("rewrite signed contract on presented registered card")
+        .set_usage("{'version':1,'imax':32,'pmax':22,'ocpp':true}")
+        .finalize()?;
+
+    let revoke_badge = AfbVerb::new("revoke-badge")
+        .set_name("revoke-badge")
+        .set_callback(revoke_badge_cb)
+        .set_context(ProvisionRqtCtx {
+            mgr,
+            scard: config.scard.clone(),
+        })
+        .set_permission(admin)
+        .set_info("revoke badge in registry")
+        .set_usage("tagid")
+        .finalize()?;
+
+    // cache dump returns clear tagid and parentIdTag
+    let cache_verb = AfbVerb::new("authorization-cache")
+        .set_name("cache")
+        .set_info("dump/clear authorization cache")
+        .set_actions("['dump','clear']")?
+        .set_callback(cache_request_cb)
+        .set_context(CacheRqtCtx { mgr })
+        .set_permission(admin)
+        .finalize()?;
+
+    let session_verb = AfbVerb::new("session")
+        .set_name("session")
+        .set_callback(session_request_cb)
+        .set_context(SessionRqtCtx { mgr })
+        .set_permission(admin)
+        .set_info("session state with clear holder name")
+        .finalize()?;
+
+    let lockout_verb = AfbVerb::new("failed-login-lockout")
+        .set_name("lockout")
+        .set_info("read/reset failed login counters")
+        .set_actions("['read','reset']")?
+        .set_callback(lockout_request_cb)
+        .set_context(LockoutRqtCtx { mgr })
+        .set_permission(admin)
+        .finalize()?;
+
+    let subscribe_lockout = AfbVerb::new("subscribe-lockout")
+        .set_callback(subscribe_callback)
+        .set_context(SubscribeData {
+            event: lockout_event,
+        })
+        .set_info("subscribe failed login lockout event")
+        .set_usage("true|false")
+        .finalize()?;
+
+    let subscribe_limit = AfbVerb::new("subscribe-limit")
+        .set_callback(subscribe_callback)
+        .set_context(SubscribeData { event: limit_event })
+        .set_info("subscribe effective charging limit event")
+        .set_usage("true|false")
+        .finalize()?;
+
+    let subscribe_security = AfbVerb::new("subscribe-security")
+        .set_callback(subscribe_callback)
+        .set_context(SubscribeData {
+            event: security_event,
+        })
+        .set_info("subscribe cloned/tampered badge alerts")
+        .set_usage("true|false")
+        .finalize()?;
+
     api.add_evt_handler(engy_handler);
     api.add_verb(auth_rqt);
     api.add_verb(auth_reset);
+    api.add_verb(auth_cancel);
     api.add_verb(subscribe);
+    api.add_verb(subscribe_security);
+    api.add_verb(subscribe_lockout);
+    api.add_verb(subscribe_limit);
     api.add_verb(state_verb);
+    api.add_verb(send_local_list);
+    api.add_verb(get_local_list_version);
+    api.add_verb(cache_verb);
+    api.add_verb(describe);
+    api.add_verb(issue_badge);
+    api.add_verb(update_contract);
+    api.add_verb(revoke_badge);
+    api.add_verb(session_verb);
+    api.add_verb(lockout_verb);
     api.add_event(event);
     api.add_event(state_event);
+    api.add_event(security_event);
+    api.add_event(lockout_event);
+    api.add_event(limit_event);
     Ok(())
 }
@@ -10,11 +10,13 @@
  *
  */
 
+use crate::prelude::*;
 use afbv4::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 AfbDataConverter!(auth_msg, AuthMsg);
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum AuthMsg {
     Done,
@@ -23,16 +25,145 @@ pub enum AuthMsg {
     Idle,
 }
 
+// how the current session was authorized
+AfbDataConverter!(auth_decision, AuthDecision);
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthDecision {
+    Unset,
+    Online,
+    Contract, // card contract does not require ocpp check
+    LocalList,
+    Cache,
+    OfflineLocal,
+    OfflineUnknown,
+    OfflineRefused,
+    FreeVend,
+}
+
+// why the last authentication failed, published within AuthState
+AfbDataConverter!(auth_reason, AuthReason);
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthReason {
+    None,
+    Blocked,
+    Expired,
+    Invalid,
+    ConcurrentTx,
+    TagidInvalid,
+    TagidFormat,
+    ContractInvalid,
+    ContractExpired,
+    ContractWindow,
+    #[serde(rename = "signature-invalid")]
+    SignatureInvalid,
+    UidMismatch,
+    Locked,
+    Refused,
+    Offline,
+    StopRefused,
+    Timeout,
+    Cancelled,
+}
+
+impl AuthReason {
+    pub fn from_status(status: OcppAuthStatus) -> Self {
+        match status {
+            OcppAuthStatus::Accepted => AuthReason::None,
+            OcppAuthStatus::Blocked => AuthReason::Blocked,
+            OcppAuthStatus::Expired => AuthReason::Expired,
+            OcppAuthStatus::Invalid => AuthReason::Invalid,
+            OcppAuthStatus::ConcurrentTx => AuthReason::ConcurrentTx,
+        }
+    }
+}
+
+// cause of an AuthMsg transition
+AfbDataConverter!(auth_trigger, AuthTrigger);
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthTrigger {
+    Login,
+    Authorized,
+    Refused,
+    Timeout,
+    Cancel,
+    Logout,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub struct AuthTransition {
+    pub from: AuthMsg,
+    pub trigger: AuthTrigger,
+    pub to: AuthMsg,
+}
+
+// authentication state machine contract as returned by describe verb
+AfbDataConverter!(auth_state_machine, AuthStateMachine);
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct AuthStateMachine {
+    pub initial: AuthMsg,
+    pub transitions: Vec<AuthTransition>,
+}
+
+// cloned or tampered badge alert pushed on security event
+AfbDataConverter!(auth_security, AuthSecurity);
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct AuthSecurity {
+    pub tagid: String,
+    pub uid: String,
+    pub reason: AuthReason,
+    pub info: String,
+}
+
+// badge issued by provisioning verbs, as recorded in uid registry
+AfbDataConverter!(auth_badge, AuthBadge);
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct AuthBadge {
+    pub tagid: String,
+    pub uid: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    pub issued: Duration,
+    #[serde(default)]
+    pub revoked: bool,
+}
+
+// issue-badge request, contract is signed by auth binding before being written
+AfbDataConverter!(auth_badge_request, AuthBadgeRequest);
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct AuthBadgeRequest {
+    pub tagid: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    pub contract: Contract,
+}
+
 AfbDataConverter!(auth_state, AuthState);
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
 pub struct AuthState {
     pub auth: AuthMsg,
     pub tagid:String,
+    pub uid: String, // card hardware uid, empty when not read
+    pub name: Option<String>, // card holder name, masked on public events
     pub imax:u32,
     pub pmax: u32,
     pub ocpp_check: bool,
     pub ocpp_auth: bool,
+    pub decision: AuthDecision,
+    pub emax: u32, // session energy budget in Wh, 0 when unlimited
+    pub reason: AuthReason,
+    pub parent_idtag: Option<String>,
+    pub tid: i32, // ocpp transaction id, 0 when no transaction is active
+    pub contract: Option<Contract>,
+    pub retry_after: u32, // seconds before a locked out login may be retried
 }
 
 impl AuthState {
@@ -42,12 +173,41 @@ impl AuthState {
             imax: 0,
             pmax: 0,
             tagid: String::new(),
+            uid: String::new(),
+            name: None,
             ocpp_check: true,
             ocpp_auth: true, // to check if the ocpp can respond false
+            decision: AuthDecision::Unset,
+            emax: 0,
+            reason: AuthReason::None,
+            parent_idtag: None,
+            tid: 0,
+            contract: None,
+            retry_after: 0,
         }
     }
 }
 
+// which limit drives the effective session current
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthLimitSource {
+    Site,
+    Contract,
+    Ocpp,
+    Budget, // session energy budget used up
+}
+
+// effective limits pushed to energy manager, published on limit event when they change
+AfbDataConverter!(auth_limit, AuthLimit);
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub struct AuthLimit {
+    pub imax: u32,
+    pub pmax: u32,
+    pub source: AuthLimitSource,
+}
+
 AfbDataConverter!(auth_actions, AuthAction);
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "lowercase", tag = "action")]
@@ -58,10 +218,94 @@ pub enum AuthAction {
     UNSUBSCRIBE,
 }
 
+AfbDataConverter!(auth_cache_actions, AuthCacheAction);
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "lowercase", tag = "action")]
+pub enum AuthCacheAction {
+    #[default]
+    DUMP,
+    CLEAR,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct AuthCacheEntry {
+    pub tagid: String,
+    pub idtag_info: OcppIdTagInfo,
+    pub updated: Duration,
+}
+
+AfbDataConverter!(auth_cache_dump, AuthCacheDump);
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct AuthCacheDump {
+    pub enabled: bool,
+    pub entries: Vec<AuthCacheEntry>,
+}
+
+AfbDataConverter!(auth_lockout_actions, AuthLockoutAction);
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "lowercase", tag = "action")]
+pub enum AuthLockoutAction {
+    #[default]
+    READ,
+    RESET,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthLockoutScope {
+    Tag,
+    Global,
+}
+
+// pushed on lockout event when failed logins reach threshold
+AfbDataConverter!(auth_lockout_event, AuthLockoutEvent);
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct AuthLockoutEvent {
+    pub scope: AuthLockoutScope,
+    pub tagid: Option<String>,
+    pub failures: u32,
+    pub level: u32,
+    pub retry_after: u32, // seconds
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct AuthLockoutCounter {
+    pub tagid: Option<String>, // None for global counter
+    pub failures: u32, // failures within window
+    pub level: u32,
+    pub retry_after: u32, // seconds, 0 when not locked
+}
+
+AfbDataConverter!(auth_lockout_dump, AuthLockoutDump);
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct AuthLockoutDump {
+    pub enabled: bool,
+    pub window: u32, // seconds
+    pub global: AuthLockoutCounter,
+    pub tags: Vec<AuthLockoutCounter>,
+}
 
 pub fn auth_registers() -> Result<(),AfbError> {
     auth_msg::register()?;
     auth_state::register()?;
+    auth_security::register()?;
+    auth_badge::register()?;
+    auth_badge_request::register()?;
+    auth_decision::register()?;
+    auth_reason::register()?;
+    auth_trigger::register()?;
+    auth_state_machine::register()?;
+    auth_limit::register()?;
     auth_actions::register()?;
+    auth_cache_actions::register()?;
+    auth_cache_dump::register()?;
+    auth_lockout_actions::register()?;
+    auth_lockout_event::register()?;
+    auth_lockout_dump::register()?;
     Ok(())
 }
\ No newline at end of file
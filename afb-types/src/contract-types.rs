@@ -0,0 +1,508 @@
+/*
+ * Copyright (C) 2015-2022 IoT.bzh Company
+ * Author: Fulup Ar Foll <fulup@iot.bzh>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ */
+
+use afbv4::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+// highest contract layout version this build understands
+pub const CONTRACT_VERSION: u32 = 1;
+
+// IEC-61851 AC current range (A) and charger power range (kW)
+pub const CONTRACT_IMAX_RANGE: (u32, u32) = (6, 80);
+pub const CONTRACT_PMAX_RANGE: (u32, u32) = (1, 350);
+
+// allowed charging window, days is a bitmask (bit0=monday) and minutes are UTC since midnight
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub struct ContractWindow {
+    pub days: u8,
+    pub start: u16,
+    pub stop: u16,
+}
+
+// subscription contract stored on badge, as returned by scard get-contract
+AfbDataConverter!(contract, Contract);
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub struct Contract {
+    pub version: u32,
+    pub imax: u32,
+    pub pmax: u32,
+    pub ocpp: bool,
+    #[serde(default)]
+    pub expiry_date: Option<Duration>,
+    #[serde(default)]
+    pub issuer: Option<String>,
+    #[serde(default)]
+    pub emax: u32, // energy cap in Wh, 0 when unlimited
+    #[serde(default)]
+    pub windows: Vec<ContractWindow>, // empty when charging is allowed anytime
+    #[serde(default)]
+    pub kid: Option<String>, // signing key id, any configured key is tried when missing
+    #[serde(default)]
+    pub signature: Option<String>, // hex encoded HMAC-SHA256 or Ed25519 signature
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContractError {
+    Version(u32),
+    Imax(u32),
+    Pmax(u32),
+    Window(usize),
+    Expired,
+    OutsideWindow,
+    Malformed(String),
+}
+
+impl fmt::Display for ContractError {
+    fn fmt(&self, format: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContractError::Version(version) => write!(
+                format,
+                "contract version:{} not supported (max:{})",
+                version, CONTRACT_VERSION
+            ),
+            ContractError::Imax(imax) => write!(
+                format,
+                "contract imax:{}A out of range [{}-{}]",
+                imax, CONTRACT_IMAX_RANGE.0, CONTRACT_IMAX_RANGE.1
+            ),
+            ContractError::Pmax(pmax) => write!(
+                format,
+                "contract pmax:{}kW out of range [{}-{}]",
+                pmax, CONTRACT_PMAX_RANGE.0, CONTRACT_PMAX_RANGE.1
+            ),
+            ContractError::Window(idx) => write!(format, "contract window:{} malformed", idx),
+            ContractError::Expired => write!(format, "contract expired"),
+            ContractError::OutsideWindow => {
+                write!(format, "contract does not allow charging at this time")
+            }
+            ContractError::Malformed(info) => write!(format, "contract malformed: {}", info),
+        }
+    }
+}
+
+impl ContractWindow {
+    fn is_valid(&self) -> bool {
+        self.days != 0 && self.days < 0x80 && self.start < self.stop && self.stop <= 1440
+    }
+
+    fn contains(&self, now: Duration) -> bool {
+        // 1970-01-01 is a thursday
+        let days = now.as_secs() / 86400;
+        let weekday = (days + 3) % 7;
+        let minutes = (now.as_secs() % 86400 / 60) as u16;
+        self.days & (1 << weekday) != 0 && minutes >= self.start && minutes < self.stop
+    }
+}
+
+impl Contract {
+    // layout and value ranges, independent of current time
+    pub fn validate(&self) -> Result<(), ContractError> {
+        if self.version == 0 || self.version > CONTRACT_VERSION {
+            return Err(ContractError::Version(self.version));
+        }
+        if self.imax < CONTRACT_IMAX_RANGE.0 || self.imax > CONTRACT_IMAX_RANGE.1 {
+            return Err(ContractError::Imax(self.imax));
+        }
+        if self.pmax < CONTRACT_PMAX_RANGE.0 || self.pmax > CONTRACT_PMAX_RANGE.1 {
+            return Err(ContractError::Pmax(self.pmax));
+        }
+        if let Some(idx) = self.windows.iter().position(|window| !window.is_valid()) {
+            return Err(ContractError::Window(idx));
+        }
+        Ok(())
+    }
+
+    // canonical byte string covered by signature, binds contract to badge tagid and card uid
+    pub fn signed_payload(&self, tagid: &str, uid: &str) -> Vec<u8> {
+        let windows: Vec<String> = self
+            .windows
+            .iter()
+            .map(|window| format!("{}:{}:{}", window.days, window.start, window.stop))
+            .collect();
+        format!(
+            "{}|{}|{}|{}|{}|{}|{}|{}|{}|{}",
+            tagid,
+            uid,
+            self.version,
+            self.imax,
+            self.pmax,
+            self.ocpp,
+            self.expiry_date.map_or(0, |expiry| expiry.as_secs()),
+            self.issuer.as_deref().unwrap_or(""),
+            self.emax,
+            windows.join(",")
+        )
+        .into_bytes()
+    }
+
+    // contract is well formed and usable at 'now'
+    pub fn check(&self, now: Duration) -> Result<(), ContractError> {
+        self.validate()?;
+        if let Some(expiry) = self.expiry_date {
+            if expiry <= now {
+                return Err(ContractError::Expired);
+            }
+        }
+        if !self.windows.is_empty() && !self.windows.iter().any(|window| window.contains(now)) {
+            return Err(ContractError::OutsideWindow);
+        }
+        Ok(())
+    }
+}
+
+// Compact contract codec for Mifare sectors (48 bytes each).
+// layout: magic, codec version, then TLV records [tag:u8][len:u8][value], integers are big endian.
+// A zero tag ends the record list, so zero padded trailing sectors are ignored and unknown tags
+// are skipped for forward compatibility. Data starting with '{' is decoded as a legacy JSON contract.
+pub const CONTRACT_MAGIC: u8 = 0xC7;
+pub const CONTRACT_CODEC_VERSION: u8 = 1;
+pub const CONTRACT_SECTOR_LEN: usize = 48;
+
+const TLV_END: u8 = 0x00;
+const TLV_VERSION: u8 = 0x01;
+const TLV_IMAX: u8 = 0x02;
+const TLV_PMAX: u8 = 0x03;
+const TLV_OCPP: u8 = 0x04;
+const TLV_EXPIRY: u8 = 0x05;
+const TLV_ISSUER: u8 = 0x06;
+const TLV_EMAX: u8 = 0x07;
+const TLV_WINDOW: u8 = 0x08;
+const TLV_KID: u8 = 0x09;
+const TLV_SIGNATURE: u8 = 0x0A;
+
+fn tlv_push(buffer: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    buffer.push(tag);
+    buffer.push(value.len() as u8);
+    buffer.extend_from_slice(value);
+}
+
+fn tlv_uint(tag: u8, value: &[u8]) -> Result<u32, ContractError> {
+    if value.is_empty() || value.len() > 4 {
+        return Err(ContractError::Malformed(format!(
+            "tag:{:#04x} invalid integer length:{}",
+            tag,
+            value.len()
+        )));
+    }
+    Ok(value.iter().fold(0, |acc, byte| (acc << 8) | *byte as u32))
+}
+
+fn tlv_string(tag: u8, value: &[u8]) -> Result<String, ContractError> {
+    match String::from_utf8(value.to_vec()) {
+        Ok(value) => Ok(value),
+        Err(_) => Err(ContractError::Malformed(format!(
+            "tag:{:#04x} invalid utf8",
+            tag
+        ))),
+    }
+}
+
+impl Contract {
+    pub fn encode(&self) -> Result<Vec<u8>, ContractError> {
+        // every value should fit its one byte length and u8/u16 fields their range
+        let strings = [&self.issuer, &self.kid];
+        if self.version > u8::MAX as u32
+            || self.imax > u8::MAX as u32
+            || self.pmax > u16::MAX as u32
+            || strings.iter().any(|value| {
+                value
+                    .as_ref()
+                    .is_some_and(|value| value.len() > u8::MAX as usize)
+            })
+        {
+            return Err(ContractError::Malformed(
+                "field too large for compact codec".to_string(),
+            ));
+        }
+
+        let mut buffer = vec![CONTRACT_MAGIC, CONTRACT_CODEC_VERSION];
+        tlv_push(&mut buffer, TLV_VERSION, &[self.version as u8]);
+        tlv_push(&mut buffer, TLV_IMAX, &[self.imax as u8]);
+        tlv_push(&mut buffer, TLV_PMAX, &(self.pmax as u16).to_be_bytes());
+        tlv_push(&mut buffer, TLV_OCPP, &[self.ocpp as u8]);
+        if let Some(expiry) = self.expiry_date {
+            tlv_push(
+                &mut buffer,
+                TLV_EXPIRY,
+                &(expiry.as_secs() as u32).to_be_bytes(),
+            );
+        }
+        if let Some(issuer) = &self.issuer {
+            tlv_push(&mut buffer, TLV_ISSUER, issuer.as_bytes());
+        }
+        if self.emax != 0 {
+            tlv_push(&mut buffer, TLV_EMAX, &self.emax.to_be_bytes());
+        }
+        for window in &self.windows {
+            let mut value = vec![window.days];
+            value.extend_from_slice(&window.start.to_be_bytes());
+            value.extend_from_slice(&window.stop.to_be_bytes());
+            tlv_push(&mut buffer, TLV_WINDOW, &value);
+        }
+        if let Some(kid) = &self.kid {
+            tlv_push(&mut buffer, TLV_KID, kid.as_bytes());
+        }
+        if let Some(signature) = &self.signature {
+            let signature = match hex::decode(signature) {
+                Ok(signature) if signature.len() <= u8::MAX as usize => signature,
+                Ok(_) => {
+                    return Err(ContractError::Malformed(
+                        "signature too large for compact codec".to_string(),
+                    ))
+                }
+                Err(error) => return Err(ContractError::Malformed(format!("signature {}", error))),
+            };
+            tlv_push(&mut buffer, TLV_SIGNATURE, &signature);
+        }
+
+        Ok(buffer)
+    }
+
+    // split encoded contract into zero padded sectors for scard set-contract
+    pub fn encode_sectors(&self, sector_len: usize) -> Result<Vec<Vec<u8>>, ContractError> {
+        let buffer = self.encode()?;
+        Ok(buffer
+            .chunks(sector_len)
+            .map(|chunk| {
+                let mut sector = chunk.to_vec();
+                sector.resize(sector_len, TLV_END);
+                sector
+            })
+            .collect())
+    }
+
+    // compact binary or legacy JSON contract
+    pub fn decode(data: &[u8]) -> Result<Self, ContractError> {
+        Self::decode_with(data, None)
+    }
+
+    // legacy JSON contracts may omit version and limits, missing ones are taken from defaults
+    pub fn decode_legacy(data: &[u8], defaults: &Contract) -> Result<Self, ContractError> {
+        Self::decode_with(data, Some(defaults))
+    }
+
+    fn decode_with(data: &[u8], defaults: Option<&Contract>) -> Result<Self, ContractError> {
+        match data.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') => {
+                let text = String::from_utf8_lossy(data);
+                let text = text.trim_end_matches(char::from(0));
+                let mut value = serde_json::from_str::<serde_json::Value>(text)
+                    .map_err(|error| ContractError::Malformed(error.to_string()))?;
+                if let (Some(defaults), Some(fields)) = (defaults, value.as_object_mut()) {
+                    fields.entry("version").or_insert(defaults.version.into());
+                    fields.entry("imax").or_insert(defaults.imax.into());
+                    fields.entry("pmax").or_insert(defaults.pmax.into());
+                    fields.entry("ocpp").or_insert(defaults.ocpp.into());
+                }
+                serde_json::from_value(value)
+                    .map_err(|error| ContractError::Malformed(error.to_string()))
+            }
+            _ => Self::decode_tlv(data),
+        }
+    }
+
+    fn decode_tlv(data: &[u8]) -> Result<Self, ContractError> {
+        if data.len() < 2 || data[0] != CONTRACT_MAGIC {
+            return Err(ContractError::Malformed(
+                "unknown contract encoding".to_string(),
+            ));
+        }
+        if data[1] > CONTRACT_CODEC_VERSION {
+            return Err(ContractError::Malformed(format!(
+                "codec version:{} not supported",
+                data[1]
+            )));
+        }
+
+        let mut version = None;
+        let mut imax = None;
+        let mut pmax = None;
+        let mut ocpp = None;
+        let mut contract = Contract {
+            version: 0,
+            imax: 0,
+            pmax: 0,
+            ocpp: false,
+            expiry_date: None,
+            issuer: None,
+            emax: 0,
+            windows: Vec::new(),
+            kid: None,
+            signature: None,
+        };
+
+        let mut idx = 2;
+        while idx < data.len() && data[idx] != TLV_END {
+            if idx + 2 > data.len() || idx + 2 + data[idx + 1] as usize > data.len() {
+                return Err(ContractError::Malformed(format!(
+                    "truncated record at:{}",
+                    idx
+                )));
+            }
+            let tag = data[idx];
+            let value = &data[idx + 2..idx + 2 + data[idx + 1] as usize];
+            idx += 2 + value.len();
+
+            match tag {
+                TLV_VERSION => version = Some(tlv_uint(tag, value)?),
+                TLV_IMAX => imax = Some(tlv_uint(tag, value)?),
+                TLV_PMAX => pmax = Some(tlv_uint(tag, value)?),
+                TLV_OCPP => ocpp = Some(tlv_uint(tag, value)? != 0),
+                TLV_EXPIRY => {
+                    contract.expiry_date = Some(Duration::from_secs(tlv_uint(tag, value)? as u64))
+                }
+                TLV_ISSUER => contract.issuer = Some(tlv_string(tag, value)?),
+                TLV_EMAX => contract.emax = tlv_uint(tag, value)?,
+                TLV_WINDOW => {
+                    if value.len() != 5 {
+                        return Err(ContractError::Malformed(
+                            "invalid window record".to_string(),
+                        ));
+                    }
+                    contract.windows.push(ContractWindow {
+                        days: value[0],
+                        start: u16::from_be_bytes([value[1], value[2]]),
+                        stop: u16::from_be_bytes([value[3], value[4]]),
+                    });
+                }
+                TLV_KID => contract.kid = Some(tlv_string(tag, value)?),
+                TLV_SIGNATURE => contract.signature = Some(hex::encode(value)),
+                _ => {} // newer field, ignored by this codec version
+            }
+        }
+
+        match (version, imax, pmax, ocpp) {
+            (Some(version), Some(imax), Some(pmax), Some(ocpp)) => {
+                contract.version = version;
+                contract.imax = imax;
+                contract.pmax = pmax;
+                contract.ocpp = ocpp;
+                Ok(contract)
+            }
+            _ => Err(ContractError::Malformed(
+                "missing version/imax/pmax/ocpp record".to_string(),
+            )),
+        }
+    }
+}
+
+pub fn contract_registers() -> Result<(), AfbError> {
+    contract::register()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contract() -> Contract {
+        Contract {
+            version: 1,
+            imax: 16,
+            pmax: 11,
+            ocpp: false,
+            expiry_date: Some(Duration::from_secs(1_900_000_000)),
+            issuer: Some("iot.bzh".to_string()),
+            emax: 20_000,
+            windows: vec![ContractWindow {
+                days: 0x1f,
+                start: 480,
+                stop: 1080,
+            }],
+            kid: Some("k1".to_string()),
+            signature: Some("00ff10ab".to_string()),
+        }
+    }
+
+    #[test]
+    fn tlv_round_trip() {
+        let contract = contract();
+        let data = contract.encode().unwrap();
+        assert_eq!(data[0], CONTRACT_MAGIC);
+        assert_eq!(Contract::decode(&data).unwrap(), contract);
+    }
+
+    #[test]
+    fn tlv_sectors_round_trip() {
+        let contract = contract();
+        let sectors = contract.encode_sectors(16).unwrap();
+        assert!(sectors.len() > 1);
+        assert!(sectors.iter().all(|sector| sector.len() == 16));
+
+        // zero padded trailing sector is ignored
+        let mut data = sectors.concat();
+        data.extend([0; 16]);
+        assert_eq!(Contract::decode(&data).unwrap(), contract);
+    }
+
+    #[test]
+    fn tlv_truncated() {
+        let data = contract().encode().unwrap();
+        for len in [3, 4, data.len() - 1] {
+            assert!(matches!(
+                Contract::decode(&data[..len]),
+                Err(ContractError::Malformed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn tlv_unknown_tag_skipped() {
+        let contract = contract();
+        let mut data = contract.encode().unwrap();
+        data.extend([0x7f, 3, 1, 2, 3]);
+        assert_eq!(Contract::decode(&data).unwrap(), contract);
+    }
+
+    #[test]
+    fn tlv_missing_record() {
+        let data = [CONTRACT_MAGIC, CONTRACT_CODEC_VERSION, TLV_VERSION, 1, 1];
+        assert!(matches!(
+            Contract::decode(&data),
+            Err(ContractError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn tlv_unknown_encoding() {
+        assert!(Contract::decode(&[0x42, 1, 0]).is_err());
+        assert!(Contract::decode(&[CONTRACT_MAGIC, CONTRACT_CODEC_VERSION + 1]).is_err());
+    }
+
+    #[test]
+    fn json_incomplete() {
+        let data = br#"{"imax":16}"#;
+        assert!(matches!(
+            Contract::decode(data),
+            Err(ContractError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn json_legacy_defaults() {
+        let mut defaults = contract();
+        defaults.pmax = 7;
+        defaults.ocpp = true;
+        let mut data = br#"{"imax":16}"#.to_vec();
+        data.extend([0; 8]);
+        let contract = Contract::decode_legacy(&data, &defaults).unwrap();
+        assert_eq!(contract.version, 1);
+        assert_eq!(contract.imax, 16);
+        assert_eq!(contract.pmax, 7);
+        assert!(contract.ocpp);
+        assert!(contract.validate().is_ok());
+    }
+}
@@ -21,6 +21,9 @@ extern crate afbv4;
 #[path = "auth-types.rs"]
 mod auth;
 
+#[path = "contract-types.rs"]
+mod contract;
+
 #[path = "ocpp-types.rs"]
 mod ocpp;
 
@@ -34,6 +37,7 @@ mod chmgr;
 pub mod prelude {
     pub use crate::engy::*;
     pub use crate::auth::*;
+    pub use crate::contract::*;
     pub use crate::ocpp::*;
     pub use crate::chmgr::*;
 }
\ No newline at end of file
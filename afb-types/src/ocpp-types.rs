@@ -13,6 +13,7 @@
 use afbv4::prelude::*;
 use crate::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 AfbDataConverter!(ocpp_msg, OcppMsg);
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -90,11 +91,81 @@ impl OcppState {
     }
 }
 
+AfbDataConverter!(ocpp_auth_status, OcppAuthStatus);
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OcppAuthStatus {
+    Accepted,
+    Blocked,
+    Expired,
+    Invalid,
+    ConcurrentTx,
+}
+
+// OCPP-1.6 IdTagInfo, expiry_date is a duration since UNIX epoch
+AfbDataConverter!(ocpp_idtag_info, OcppIdTagInfo);
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct OcppIdTagInfo {
+    pub status: OcppAuthStatus,
+    pub expiry_date: Option<Duration>,
+    pub parent_idtag: Option<String>,
+}
+
+impl OcppIdTagInfo {
+    // tag is accepted and not expired at 'now'
+    pub fn is_valid(&self, now: Duration) -> bool {
+        match self.expiry_date {
+            Some(expiry) if expiry <= now => false,
+            _ => self.status == OcppAuthStatus::Accepted,
+        }
+    }
+}
+
+// local authorization list entry, no idtag_info means remove on differential update
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct OcppAuthData {
+    pub idtag: String,
+    pub idtag_info: Option<OcppIdTagInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OcppUpdateType {
+    Full,
+    Differential,
+}
+
+// OCPP-1.6 SendLocalList request
+AfbDataConverter!(ocpp_local_list, OcppLocalList);
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct OcppLocalList {
+    pub version: i32,
+    pub update: OcppUpdateType,
+    pub list: Vec<OcppAuthData>,
+}
+
+AfbDataConverter!(ocpp_update_status, OcppUpdateStatus);
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OcppUpdateStatus {
+    Accepted,
+    Failed,
+    NotSupported,
+    VersionMismatch,
+}
+
 pub fn ocpp_registers() -> Result<(), AfbError> {
     ocpp_msg::register()?;
     ocpp_state::register()?;
     ocpp_transaction::register()?;
     ocpp_charger_status::register()?;
+    ocpp_auth_status::register()?;
+    ocpp_idtag_info::register()?;
+    ocpp_local_list::register()?;
+    ocpp_update_status::register()?;
 
     Ok(())
 }
@@ -0,0 +1,647 @@
+/*
+ * Copyright (C) 2015-2022 IoT.bzh Company
+ * Author: Fulup Ar Foll <fulup@iot.bzh>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ */
+use crate::prelude::*;
+use afbv4::prelude::*;
+use std::sync::Mutex;
+use typesv4::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthBackendStatus {
+    Accepted,
+    Refused,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthStage {
+    Identify,
+    Authorize,
+    Start,
+    Stop,
+}
+
+// backend step outcome
+pub enum AuthStep {
+    Next, // backend has another subcall for this stage
+    Done(AuthBackendStatus),
+    Authorized(OcppIdTagInfo),
+}
+
+pub type AuthSubcallCb = fn(&AfbRequest, &AfbRqtData, &AfbCtxData) -> Result<(), AfbError>;
+
+// subcall argument, each variant maps to a registered afb converter
+pub enum AuthSubcallArg {
+    Flag(bool),
+    Text(String), // tagid or card holder name
+    Transaction(OcppTransaction),
+    Energy(EnergyAction),
+    Status(OcppChargerStatus),
+    Json(JsoncObj),
+}
+
+pub struct AuthSubcall {
+    pub api: &'static str,
+    pub verb: &'static str,
+    pub arg: AuthSubcallArg,
+}
+
+impl AuthSubcall {
+    pub fn new(api: &'static str, verb: &'static str, arg: AuthSubcallArg) -> Self {
+        AuthSubcall { api, verb, arg }
+    }
+
+    pub fn call_sync(self, apiv4: AfbApiV4) -> Result<AfbRqtData, AfbError> {
+        match self.arg {
+            AuthSubcallArg::Flag(value) => AfbSubCall::call_sync(apiv4, self.api, self.verb, value),
+            AuthSubcallArg::Text(value) => AfbSubCall::call_sync(apiv4, self.api, self.verb, value),
+            AuthSubcallArg::Transaction(value) => {
+                AfbSubCall::call_sync(apiv4, self.api, self.verb, value)
+            }
+            AuthSubcallArg::Energy(value) => {
+                AfbSubCall::call_sync(apiv4, self.api, self.verb, value)
+            }
+            AuthSubcallArg::Status(value) => {
+                AfbSubCall::call_sync(apiv4, self.api, self.verb, value)
+            }
+            AuthSubcallArg::Json(value) => AfbSubCall::call_sync(apiv4, self.api, self.verb, value),
+        }
+    }
+
+    pub fn call_async<T: 'static>(
+        self,
+        rqt: &AfbRequest,
+        callback: AuthSubcallCb,
+        ctx: T,
+    ) -> Result<(), AfbError> {
+        match self.arg {
+            AuthSubcallArg::Flag(value) => {
+                AfbSubCall::call_async(rqt, self.api, self.verb, value, callback, ctx)
+            }
+            AuthSubcallArg::Text(value) => {
+                AfbSubCall::call_async(rqt, self.api, self.verb, value, callback, ctx)
+            }
+            AuthSubcallArg::Transaction(value) => {
+                AfbSubCall::call_async(rqt, self.api, self.verb, value, callback, ctx)
+            }
+            AuthSubcallArg::Energy(value) => {
+                AfbSubCall::call_async(rqt, self.api, self.verb, value, callback, ctx)
+            }
+            AuthSubcallArg::Status(value) => {
+                AfbSubCall::call_async(rqt, self.api, self.verb, value, callback, ctx)
+            }
+            AuthSubcallArg::Json(value) => {
+                AfbSubCall::call_async(rqt, self.api, self.verb, value, callback, ctx)
+            }
+        }
+    }
+}
+
+// Authentication backend. Each stage is a sequence of subcalls, ManagerHandle runs them
+// asynchronously for login and synchronously for logout. Backends are chained in configuration order:
+//  - identify: first backend returning Done(Accepted) provides tagid+contract, Done(Refused)
+//    stops the chain, a backend without identify subcall leaves it to next one
+//  - authorize: every backend is called, any non accepted IdTagInfo fails the login
+//  - start/stop transaction: every backend is called
+pub trait AuthBackend: Send + Sync {
+    fn get_uid(&self) -> &'static str;
+
+    // subcall for stage step, None when backend has nothing (more) to do
+    fn get_subcall(
+        &self,
+        _stage: AuthStage,
+        _step: usize,
+        _data_set: &AuthState,
+    ) -> Option<AuthSubcall> {
+        None
+    }
+
+    fn on_response(
+        &self,
+        _stage: AuthStage,
+        _step: usize,
+        _data_set: &mut AuthState,
+        _response: &AfbRqtData,
+    ) -> Result<AuthStep, AfbError> {
+        Ok(AuthStep::Next)
+    }
+
+    fn on_error(
+        &self,
+        _stage: AuthStage,
+        _step: usize,
+        _data_set: &mut AuthState,
+        error: AfbError,
+    ) -> Result<AuthStep, AfbError> {
+        Err(error)
+    }
+
+    fn update_engy_state(
+        &self,
+        _apiv4: AfbApiV4,
+        _engy_state: &EnergyState,
+    ) -> Result<(), AfbError> {
+        Ok(())
+    }
+}
+
+// sector data comes as a byte array (compact codec), a string or a legacy json contract object
+pub fn scard_bytes(jsonc: &JsoncObj) -> Vec<u8> {
+    let data = jsonc.to_string();
+    if let Ok(bytes) = serde_json::from_str::<Vec<u8>>(&data) {
+        return bytes;
+    }
+    match serde_json::from_str::<String>(&data) {
+        Ok(text) => text.into_bytes(),
+        Err(_) => data.into_bytes(),
+    }
+}
+
+// scard command pair reading/writing one card sector
+#[derive(Clone)]
+pub struct ScardSector {
+    pub get: &'static str,
+    pub set: &'static str,
+}
+
+// failed read of a required card step
+#[derive(Clone, Copy, PartialEq)]
+pub enum ScardOnError {
+    Retry,  // card may have been removed, read it again until login timeout
+    Refuse, // refuse login immediately
+}
+
+impl ScardOnError {
+    pub fn parse(on_error: &str) -> Result<Self, AfbError> {
+        let on_error = match on_error.to_lowercase().as_str() {
+            "retry" => ScardOnError::Retry,
+            "refuse" => ScardOnError::Refuse,
+            _ => {
+                return afb_error!(
+                    "scard-config-fail",
+                    "on_error:{} should be retry|refuse",
+                    on_error
+                )
+            }
+        };
+        Ok(on_error)
+    }
+}
+
+// logical card step mapped on reader verbs, failed optional steps are skipped
+#[derive(Clone)]
+pub struct ScardStepCfg {
+    pub sectors: Vec<ScardSector>, // compact contracts may span several sectors
+    pub required: bool,
+    pub on_error: ScardOnError,
+}
+
+impl ScardStepCfg {
+    pub fn new(get: &'static str, set: &'static str, required: bool) -> Self {
+        ScardStepCfg {
+            sectors: vec![ScardSector { get, set }],
+            required,
+            on_error: ScardOnError::Retry,
+        }
+    }
+
+    pub fn get(&self) -> &'static str {
+        self.sectors[0].get
+    }
+
+    pub fn set(&self) -> &'static str {
+        self.sectors[0].set
+    }
+}
+
+// scard api and verbs used to read and provision badges
+#[derive(Clone)]
+pub struct ScardVerbs {
+    pub api: &'static str,
+    pub tag: ScardStepCfg,
+    pub contract: ScardStepCfg,
+    pub uid: ScardStepCfg,
+    pub name: ScardStepCfg,
+}
+
+// optional card reads after tagid, from binding config defaults
+#[derive(Clone, Copy, PartialEq)]
+pub enum ScardRead {
+    Contract,
+    Uid,
+    Name,
+}
+
+impl ScardRead {
+    pub fn parse(read: &str) -> Result<Self, AfbError> {
+        let read = match read.to_lowercase().as_str() {
+            "contract" => ScardRead::Contract,
+            "uid" => ScardRead::Uid,
+            "name" => ScardRead::Name,
+            _ => {
+                return afb_error!(
+                    "scard-config-fail",
+                    "card read:{} should be contract|uid|name",
+                    read
+                )
+            }
+        };
+        Ok(read)
+    }
+}
+
+// how the card is identified, cards without data sector are identified by their hardware uid
+#[derive(Clone, Copy, PartialEq)]
+pub enum ScardIdentify {
+    Tagid,
+    Uid { reverse: bool }, // reverse reader byte order
+}
+
+// card reads performed at identification, in order
+#[derive(Clone, Copy)]
+enum ScardStep {
+    Tagid,
+    UidTag,          // hardware uid used as tagid
+    Contract(usize), // contract sector index
+    Uid,
+    Name,
+}
+
+// NFC badge identification through scard api (tagid + subscription contract)
+pub struct ScardBackend {
+    verbs: ScardVerbs,
+    identify: ScardIdentify,
+    steps: Vec<ScardStep>,
+    legacy: Option<Contract>, // defaults filling incomplete legacy JSON contracts
+    // contract sectors read so far, None once an optional contract read failed,
+    // a single reader serves one login at a time
+    sectors: Mutex<Option<Vec<u8>>>,
+}
+
+impl ScardBackend {
+    pub fn new(
+        verbs: ScardVerbs,
+        identify: ScardIdentify,
+        reads: &[ScardRead],
+        legacy: Option<Contract>,
+    ) -> Box<Self> {
+        let mut steps = match identify {
+            ScardIdentify::Tagid => vec![ScardStep::Tagid],
+            ScardIdentify::Uid { .. } => vec![ScardStep::UidTag],
+        };
+        for read in reads {
+            match (identify, read) {
+                // uid only cards have no contract, limits come from local list or site defaults
+                (ScardIdentify::Uid { .. }, ScardRead::Contract | ScardRead::Uid) => {}
+                (_, ScardRead::Contract) => {
+                    steps.extend((0..verbs.contract.sectors.len()).map(ScardStep::Contract))
+                }
+                (_, ScardRead::Uid) => steps.push(ScardStep::Uid),
+                (_, ScardRead::Name) => steps.push(ScardStep::Name),
+            }
+        }
+
+        Box::new(ScardBackend {
+            verbs,
+            identify,
+            steps,
+            legacy,
+            sectors: Mutex::new(None),
+        })
+    }
+
+    fn step_cfg(&self, step: ScardStep) -> &ScardStepCfg {
+        match step {
+            ScardStep::Tagid => &self.verbs.tag,
+            ScardStep::Contract(_) => &self.verbs.contract,
+            ScardStep::Uid | ScardStep::UidTag => &self.verbs.uid,
+            ScardStep::Name => &self.verbs.name,
+        }
+    }
+
+    // optional step is skipped, required one is retried or refused as configured
+    fn step_fail(
+        &self,
+        read: ScardStep,
+        step: usize,
+        data_set: &mut AuthState,
+        info: String,
+    ) -> Result<AuthStep, AfbError> {
+        let (reason, what) = match read {
+            ScardStep::Tagid => {
+                data_set.tagid = String::new();
+                (AuthReason::TagidInvalid, "tagid")
+            }
+            ScardStep::Contract(_) => {
+                *self.sectors.lock().unwrap() = None;
+                data_set.contract = None;
+                (AuthReason::ContractInvalid, "subscription contract")
+            }
+            ScardStep::Uid => {
+                data_set.uid = String::new();
+                (AuthReason::TagidInvalid, "card uid")
+            }
+            ScardStep::UidTag => {
+                data_set.uid = String::new();
+                data_set.tagid = String::new();
+                (AuthReason::TagidInvalid, "uid tagid")
+            }
+            ScardStep::Name => {
+                data_set.name = None;
+                (AuthReason::Invalid, "holder name")
+            }
+        };
+
+        // card identification can never be skipped
+        let cfg = self.step_cfg(read);
+        if !cfg.required && !matches!(read, ScardStep::UidTag) {
+            afb_log_msg!(Debug, None, "optional card {} skipped: {}", what, info);
+            return Ok(self.step_done(step));
+        }
+
+        data_set.reason = reason;
+        match cfg.on_error {
+            ScardOnError::Retry => afb_error!("auth-login-fail", "invalid card {}: {}", what, info),
+            ScardOnError::Refuse => {
+                afb_log_msg!(Notice, None, "invalid card {}: {}", what, info);
+                Ok(AuthStep::Done(AuthBackendStatus::Refused))
+            }
+        }
+    }
+
+    // last read identifies the card
+    fn step_done(&self, step: usize) -> AuthStep {
+        if step + 1 < self.steps.len() {
+            AuthStep::Next
+        } else {
+            AuthStep::Done(AuthBackendStatus::Accepted)
+        }
+    }
+}
+
+impl AuthBackend for ScardBackend {
+    fn get_uid(&self) -> &'static str {
+        "nfc"
+    }
+
+    fn get_subcall(
+        &self,
+        stage: AuthStage,
+        step: usize,
+        _data_set: &AuthState,
+    ) -> Option<AuthSubcall> {
+        if stage != AuthStage::Identify {
+            return None;
+        }
+
+        let verb = match self.steps.get(step)? {
+            ScardStep::Contract(sector) => self.verbs.contract.sectors[*sector].get,
+            read => self.step_cfg(*read).get(),
+        };
+        Some(AuthSubcall::new(
+            self.verbs.api,
+            verb,
+            AuthSubcallArg::Flag(true),
+        ))
+    }
+
+    fn on_response(
+        &self,
+        stage: AuthStage,
+        step: usize,
+        data_set: &mut AuthState,
+        response: &AfbRqtData,
+    ) -> Result<AuthStep, AfbError> {
+        let read = match (stage, self.steps.get(step)) {
+            (AuthStage::Identify, Some(read)) => *read,
+            _ => return Ok(AuthStep::Next),
+        };
+
+        match read {
+            ScardStep::Tagid => match response.get::<String>(0) {
+                Ok(nfc_data) => data_set.tagid = nfc_data,
+                Err(error) => return self.on_error(stage, step, data_set, error),
+            },
+            ScardStep::Contract(sector) => {
+                let jsonc = match response.get::<JsoncObj>(0) {
+                    Ok(jsonc) => jsonc,
+                    Err(error) => return self.on_error(stage, step, data_set, error),
+                };
+
+                let mut sectors = self.sectors.lock().unwrap();
+                if sector == 0 {
+                    *sectors = Some(Vec::new());
+                }
+                // remaining sectors of a skipped optional contract
+                let buffer = match &mut *sectors {
+                    Some(buffer) => buffer,
+                    None => return Ok(self.step_done(step)),
+                };
+                buffer.extend(scard_bytes(&jsonc));
+                if sector + 1 < self.verbs.contract.sectors.len() {
+                    return Ok(AuthStep::Next);
+                }
+
+                let contract = match &self.legacy {
+                    Some(defaults) => Contract::decode_legacy(buffer, defaults),
+                    None => Contract::decode(buffer),
+                };
+                *sectors = None;
+                drop(sectors);
+
+                match contract {
+                    // contract is validated by manager before authorization
+                    Ok(contract) => data_set.contract = Some(contract),
+                    // malformed or tampered data, neither retried nor skipped whatever the policy
+                    Err(error) => {
+                        afb_log_msg!(
+                            Notice,
+                            None,
+                            "invalid card subscription contract: {}",
+                            error
+                        );
+                        data_set.contract = None;
+                        data_set.reason = AuthReason::ContractInvalid;
+                        return Ok(AuthStep::Done(AuthBackendStatus::Refused));
+                    }
+                }
+            }
+            ScardStep::Uid => match response.get::<String>(0) {
+                Ok(uid) => data_set.uid = uid,
+                Err(error) => return self.on_error(stage, step, data_set, error),
+            },
+            ScardStep::UidTag => {
+                let uid = match response.get::<String>(0) {
+                    Ok(uid) => uid,
+                    Err(error) => return self.on_error(stage, step, data_set, error),
+                };
+                let reverse = matches!(self.identify, ScardIdentify::Uid { reverse: true });
+                match uid_to_idtag(&uid, reverse) {
+                    Ok(idtag) => {
+                        data_set.tagid = idtag;
+                        data_set.uid = uid;
+                    }
+                    Err(error) => return self.step_fail(read, step, data_set, error.to_string()),
+                }
+            }
+            ScardStep::Name => {
+                let name = match response.get::<String>(0) {
+                    Ok(name) => name,
+                    Err(error) => return self.on_error(stage, step, data_set, error),
+                };
+                // sector is zero padded, empty sector means no holder name
+                let name = name.trim_end_matches('\0').trim();
+                data_set.name = (!name.is_empty()).then(|| name.to_string());
+            }
+        }
+        Ok(self.step_done(step))
+    }
+
+    fn on_error(
+        &self,
+        stage: AuthStage,
+        step: usize,
+        data_set: &mut AuthState,
+        error: AfbError,
+    ) -> Result<AuthStep, AfbError> {
+        let read = match (stage, self.steps.get(step)) {
+            (AuthStage::Identify, Some(read)) => *read,
+            _ => return Err(error),
+        };
+        self.step_fail(read, step, data_set, error.to_string())
+    }
+}
+
+// OCPP central system authorization and transaction accounting
+pub struct OcppBackend {
+    ocpp_api: &'static str,
+}
+
+impl OcppBackend {
+    pub fn new(ocpp_api: &'static str) -> Box<Self> {
+        Box::new(OcppBackend { ocpp_api })
+    }
+}
+
+impl AuthBackend for OcppBackend {
+    fn get_uid(&self) -> &'static str {
+        "ocpp"
+    }
+
+    fn get_subcall(
+        &self,
+        stage: AuthStage,
+        step: usize,
+        data_set: &AuthState,
+    ) -> Option<AuthSubcall> {
+        // badge contract does not require ocpp check
+        if !data_set.ocpp_check {
+            return None;
+        }
+
+        let subcall = match (stage, step) {
+            (AuthStage::Authorize, 0) => AuthSubcall::new(
+                self.ocpp_api,
+                "authorize",
+                AuthSubcallArg::Text(data_set.tagid.clone()),
+            ),
+            (AuthStage::Start, 0) => AuthSubcall::new(
+                self.ocpp_api,
+                "transaction",
+                AuthSubcallArg::Transaction(OcppTransaction::Start(data_set.tagid.clone())),
+            ),
+            (AuthStage::Stop, 0) => AuthSubcall::new(
+                self.ocpp_api,
+                "transaction",
+                AuthSubcallArg::Transaction(OcppTransaction::Stop(data_set.tid)),
+            ),
+            (AuthStage::Stop, 1) => AuthSubcall::new(
+                self.ocpp_api,
+                "status-notification",
+                AuthSubcallArg::Status(OcppChargerStatus::Available),
+            ),
+            _ => return None,
+        };
+        Some(subcall)
+    }
+
+    fn on_response(
+        &self,
+        stage: AuthStage,
+        step: usize,
+        data_set: &mut AuthState,
+        response: &AfbRqtData,
+    ) -> Result<AuthStep, AfbError> {
+        match (stage, step) {
+            (AuthStage::Authorize, 0) => {
+                // ocpp binding returns IdTagInfo, legacy version only returns a bool
+                let idtag_info = match response.get::<&OcppIdTagInfo>(0) {
+                    Ok(idtag_info) => idtag_info.clone(),
+                    Err(_) => {
+                        let status = if response.get::<bool>(0)? {
+                            OcppAuthStatus::Accepted
+                        } else {
+                            OcppAuthStatus::Invalid
+                        };
+                        OcppIdTagInfo {
+                            status,
+                            expiry_date: None,
+                            parent_idtag: None,
+                        }
+                    }
+                };
+
+                if idtag_info.status != OcppAuthStatus::Accepted {
+                    afb_log_msg!(
+                        Notice,
+                        None,
+                        "::::::::NFC rejected by OCPP status:{:?}::::::::",
+                        idtag_info.status
+                    );
+                }
+                Ok(AuthStep::Authorized(idtag_info))
+            }
+            (AuthStage::Start, 0) => {
+                // keep transaction id for logout
+                match response.get::<i32>(0) {
+                    Ok(tid) => data_set.tid = tid,
+                    Err(_) => afb_log_msg!(
+                        Warning,
+                        None,
+                        "ocpp transaction start returned no transaction id"
+                    ),
+                }
+                Ok(AuthStep::Done(AuthBackendStatus::Accepted))
+            }
+            (AuthStage::Stop, 1) => Ok(AuthStep::Done(AuthBackendStatus::Accepted)),
+            _ => Ok(AuthStep::Next),
+        }
+    }
+
+    fn on_error(
+        &self,
+        stage: AuthStage,
+        _step: usize,
+        _data_set: &mut AuthState,
+        error: AfbError,
+    ) -> Result<AuthStep, AfbError> {
+        match stage {
+            AuthStage::Authorize => {
+                afb_error!("ocpp-login-fail", "::::::::OCPP fails to authorize::::::::")
+            }
+            _ => Err(error),
+        }
+    }
+
+    fn update_engy_state(&self, apiv4: AfbApiV4, engy_state: &EnergyState) -> Result<(), AfbError> {
+        AfbSubCall::call_sync(apiv4, self.ocpp_api, "push-measure", engy_state.clone())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+/*
+ * Copyright (C) 2015-2022 IoT.bzh Company
+ * Author: Fulup Ar Foll <fulup@iot.bzh>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ */
+
+use std::collections::HashMap;
+use std::time::Duration;
+use typesv4::prelude::*;
+
+struct CacheEntry {
+    idtag_info: OcppIdTagInfo,
+    updated: Duration,
+    last_used: u64,
+}
+
+// OCPP-1.6 authorization cache, entries are evicted by age then least recently used
+pub struct AuthCache {
+    enabled: bool,
+    size: usize,
+    max_age: Duration,
+    counter: u64,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl AuthCache {
+    pub fn new(enabled: bool, size: usize, max_age: Duration) -> Self {
+        AuthCache {
+            enabled,
+            size,
+            max_age,
+            counter: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // drop entries older than max_age or past their expiry date
+    fn purge(&mut self, now: Duration) {
+        let max_age = self.max_age;
+        self.entries.retain(|_, entry| {
+            let young = now.saturating_sub(entry.updated) < max_age;
+            let valid = match entry.idtag_info.expiry_date {
+                Some(expiry) => expiry > now,
+                None => true,
+            };
+            young && valid
+        });
+    }
+
+    pub fn get(&mut self, tagid: &str, now: Duration) -> Option<OcppIdTagInfo> {
+        if !self.enabled {
+            return None;
+        }
+        self.purge(now);
+        self.counter += 1;
+        let counter = self.counter;
+        self.entries.get_mut(tagid).map(|entry| {
+            entry.last_used = counter;
+            entry.idtag_info.clone()
+        })
+    }
+
+    pub fn update(&mut self, tagid: &str, idtag_info: OcppIdTagInfo, now: Duration) {
+        if !self.enabled || self.size == 0 {
+            return;
+        }
+        self.purge(now);
+
+        // cache full, evict least recently used entry
+        if !self.entries.contains_key(tagid) && self.entries.len() >= self.size {
+            let lru = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = lru {
+                self.entries.remove(&key);
+            }
+        }
+
+        self.counter += 1;
+        self.entries.insert(
+            tagid.to_string(),
+            CacheEntry {
+                idtag_info,
+                updated: now,
+                last_used: self.counter,
+            },
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn dump(&self) -> AuthCacheDump {
+        AuthCacheDump {
+            enabled: self.enabled,
+            entries: self
+                .entries
+                .iter()
+                .map(|(tagid, entry)| AuthCacheEntry {
+                    tagid: tagid.clone(),
+                    idtag_info: entry.idtag_info.clone(),
+                    updated: entry.updated,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn accepted(expiry_date: Option<Duration>) -> OcppIdTagInfo {
+        OcppIdTagInfo {
+            status: OcppAuthStatus::Accepted,
+            expiry_date,
+            parent_idtag: None,
+        }
+    }
+
+    #[test]
+    fn disabled() {
+        let mut cache = AuthCache::new(false, 4, secs(60));
+        cache.update("tag-a", accepted(None), secs(0));
+        assert!(cache.get("tag-a", secs(1)).is_none());
+        assert!(cache.dump().entries.is_empty());
+    }
+
+    #[test]
+    fn max_age() {
+        let mut cache = AuthCache::new(true, 4, secs(60));
+        cache.update("tag-a", accepted(None), secs(0));
+        assert!(cache.get("tag-a", secs(59)).is_some());
+        assert!(cache.get("tag-a", secs(60)).is_none());
+        assert!(cache.dump().entries.is_empty());
+    }
+
+    #[test]
+    fn expiry_date() {
+        let mut cache = AuthCache::new(true, 4, secs(3600));
+        cache.update("tag-a", accepted(Some(secs(10))), secs(0));
+        assert!(cache.get("tag-a", secs(9)).is_some());
+        assert!(cache.get("tag-a", secs(10)).is_none());
+    }
+
+    #[test]
+    fn lru_eviction() {
+        let mut cache = AuthCache::new(true, 2, secs(3600));
+        cache.update("tag-a", accepted(None), secs(0));
+        cache.update("tag-b", accepted(None), secs(1));
+        // tag-a is used again, tag-b becomes least recently used
+        assert!(cache.get("tag-a", secs(2)).is_some());
+        cache.update("tag-c", accepted(None), secs(3));
+
+        assert!(cache.get("tag-a", secs(4)).is_some());
+        assert!(cache.get("tag-b", secs(4)).is_none());
+        assert!(cache.get("tag-c", secs(4)).is_some());
+    }
+
+    #[test]
+    fn update_existing_does_not_evict() {
+        let mut cache = AuthCache::new(true, 2, secs(3600));
+        cache.update("tag-a", accepted(None), secs(0));
+        cache.update("tag-b", accepted(None), secs(1));
+        cache.update("tag-a", accepted(None), secs(2));
+        assert_eq!(cache.dump().entries.len(), 2);
+
+        cache.clear();
+        assert!(cache.get("tag-a", secs(3)).is_none());
+    }
+}
@@ -0,0 +1,145 @@
+/*
+ * Copyright (C) 2015-2022 IoT.bzh Company
+ * Author: Fulup Ar Foll <fulup@iot.bzh>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ */
+
+use afbv4::prelude::*;
+use typesv4::prelude::*;
+
+// legal AuthMsg transitions, anything not listed is rejected
+const AUTH_TRANSITIONS: [AuthTransition; 9] = [
+    AuthTransition {
+        from: AuthMsg::Idle,
+        trigger: AuthTrigger::Login,
+        to: AuthMsg::Pending,
+    },
+    AuthTransition {
+        from: AuthMsg::Fail,
+        trigger: AuthTrigger::Login,
+        to: AuthMsg::Pending,
+    },
+    // single sign on, session remains active
+    AuthTransition {
+        from: AuthMsg::Done,
+        trigger: AuthTrigger::Login,
+        to: AuthMsg::Done,
+    },
+    AuthTransition {
+        from: AuthMsg::Pending,
+        trigger: AuthTrigger::Authorized,
+        to: AuthMsg::Done,
+    },
+    AuthTransition {
+        from: AuthMsg::Pending,
+        trigger: AuthTrigger::Refused,
+        to: AuthMsg::Fail,
+    },
+    AuthTransition {
+        from: AuthMsg::Pending,
+        trigger: AuthTrigger::Timeout,
+        to: AuthMsg::Idle,
+    },
+    AuthTransition {
+        from: AuthMsg::Pending,
+        trigger: AuthTrigger::Cancel,
+        to: AuthMsg::Idle,
+    },
+    AuthTransition {
+        from: AuthMsg::Done,
+        trigger: AuthTrigger::Logout,
+        to: AuthMsg::Idle,
+    },
+    AuthTransition {
+        from: AuthMsg::Fail,
+        trigger: AuthTrigger::Logout,
+        to: AuthMsg::Idle,
+    },
+];
+
+// target state for trigger, illegal transitions fail with 'auth-transition-refused'
+pub fn auth_transition(from: AuthMsg, trigger: AuthTrigger) -> Result<AuthMsg, AfbError> {
+    match AUTH_TRANSITIONS
+        .iter()
+        .find(|transition| transition.from == from && transition.trigger == trigger)
+    {
+        Some(transition) => Ok(transition.to),
+        None => afb_error!(
+            "auth-transition-refused",
+            "illegal transition from:{:?} trigger:{:?}",
+            from,
+            trigger
+        ),
+    }
+}
+
+pub fn auth_describe() -> AuthStateMachine {
+    AuthStateMachine {
+        initial: AuthMsg::Idle,
+        transitions: AUTH_TRANSITIONS.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATES: [AuthMsg; 4] = [
+        AuthMsg::Idle,
+        AuthMsg::Pending,
+        AuthMsg::Done,
+        AuthMsg::Fail,
+    ];
+    const TRIGGERS: [AuthTrigger; 6] = [
+        AuthTrigger::Login,
+        AuthTrigger::Authorized,
+        AuthTrigger::Refused,
+        AuthTrigger::Timeout,
+        AuthTrigger::Cancel,
+        AuthTrigger::Logout,
+    ];
+
+    fn expected(from: AuthMsg, trigger: AuthTrigger) -> Option<AuthMsg> {
+        match (from, trigger) {
+            (AuthMsg::Idle | AuthMsg::Fail, AuthTrigger::Login) => Some(AuthMsg::Pending),
+            (AuthMsg::Done, AuthTrigger::Login) => Some(AuthMsg::Done),
+            (AuthMsg::Pending, AuthTrigger::Authorized) => Some(AuthMsg::Done),
+            (AuthMsg::Pending, AuthTrigger::Refused) => Some(AuthMsg::Fail),
+            (AuthMsg::Pending, AuthTrigger::Timeout | AuthTrigger::Cancel) => Some(AuthMsg::Idle),
+            (AuthMsg::Done | AuthMsg::Fail, AuthTrigger::Logout) => Some(AuthMsg::Idle),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn transition_table() {
+        for from in STATES {
+            for trigger in TRIGGERS {
+                let to = auth_transition(from, trigger).ok();
+                assert_eq!(to, expected(from, trigger), "{:?} {:?}", from, trigger);
+            }
+        }
+    }
+
+    #[test]
+    fn refused_transitions() {
+        assert!(auth_transition(AuthMsg::Idle, AuthTrigger::Logout).is_err());
+        assert!(auth_transition(AuthMsg::Pending, AuthTrigger::Login).is_err());
+        assert!(auth_transition(AuthMsg::Pending, AuthTrigger::Logout).is_err());
+        assert!(auth_transition(AuthMsg::Done, AuthTrigger::Authorized).is_err());
+        assert!(auth_transition(AuthMsg::Idle, AuthTrigger::Timeout).is_err());
+    }
+
+    #[test]
+    fn describe() {
+        let machine = auth_describe();
+        assert_eq!(machine.initial, AuthMsg::Idle);
+        assert_eq!(machine.transitions.len(), AUTH_TRANSITIONS.len());
+    }
+}
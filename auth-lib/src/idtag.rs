@@ -0,0 +1,270 @@
+/*
+ * Copyright (C) 2015-2022 IoT.bzh Company
+ * Author: Fulup Ar Foll <fulup@iot.bzh>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ */
+
+use afbv4::prelude::*;
+
+// OCPP-1.6 idTag is a CiString20Type
+pub const IDTAG_MAX_LEN: usize = 20;
+
+// card hardware uid as OCPP idTag, uppercase hex without separators. Readers returning
+// uid LSB first should set reverse to get the ISO 14443 byte order.
+pub fn uid_to_idtag(uid: &str, reverse: bool) -> Result<String, AfbError> {
+    let digits: String = uid
+        .trim_end_matches('\0')
+        .chars()
+        .filter(|digit| !matches!(digit, ':' | '-' | ' '))
+        .collect();
+
+    let mut bytes = match hex::decode(&digits) {
+        Ok(bytes) if !bytes.is_empty() => bytes,
+        Ok(_) => return afb_error!("idtag-invalid", "empty card uid"),
+        Err(error) => return afb_error!("idtag-invalid", "card uid is not hexadecimal: {}", error),
+    };
+    if reverse {
+        bytes.reverse();
+    }
+
+    let idtag = hex::encode_upper(bytes);
+    if idtag.len() > IDTAG_MAX_LEN {
+        return afb_error!(
+            "idtag-invalid",
+            "card uid needs {} characters, idTag is limited to {}",
+            idtag.len(),
+            IDTAG_MAX_LEN
+        );
+    }
+    Ok(idtag)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum IdTagCase {
+    Keep,
+    Upper,
+    Lower,
+}
+
+impl IdTagCase {
+    pub fn parse(case: &str) -> Result<Self, AfbError> {
+        let case = match case.to_lowercase().as_str() {
+            "keep" => IdTagCase::Keep,
+            "upper" => IdTagCase::Upper,
+            "lower" => IdTagCase::Lower,
+            _ => {
+                return afb_error!(
+                    "idtag-config-fail",
+                    "case:{} should be keep|upper|lower",
+                    case
+                )
+            }
+        };
+        Ok(case)
+    }
+}
+
+// accepted tag formats, tags matching none of the configured formats are refused
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IdTagFormat {
+    Iso14443, // 4, 7 or 10 bytes hardware uid in hex
+    Emaid,    // ISO 15118 / DIN 91286 e-mobility account id, check digit is not verified
+}
+
+impl IdTagFormat {
+    pub fn parse(format: &str) -> Result<Self, AfbError> {
+        let format = match format.to_lowercase().as_str() {
+            "iso14443" => IdTagFormat::Iso14443,
+            "emaid" => IdTagFormat::Emaid,
+            _ => {
+                return afb_error!(
+                    "idtag-config-fail",
+                    "format:{} should be iso14443|emaid",
+                    format
+                )
+            }
+        };
+        Ok(format)
+    }
+
+    // normalized tag when it matches format
+    fn check(&self, tagid: &str) -> Option<String> {
+        match self {
+            IdTagFormat::Iso14443 => {
+                let valid = matches!(tagid.len(), 8 | 14 | 20)
+                    && tagid.chars().all(|digit| digit.is_ascii_hexdigit());
+                valid.then(|| tagid.to_string())
+            }
+            // CC-PPP-XXXXXXXXX(-C), dashes are optional
+            IdTagFormat::Emaid => {
+                let emaid: String = tagid.chars().filter(|char| *char != '-').collect();
+                let valid = matches!(emaid.len(), 14 | 15)
+                    && emaid.chars().take(2).all(|char| char.is_ascii_alphabetic())
+                    && emaid.chars().all(|char| char.is_ascii_alphanumeric());
+                valid.then_some(emaid)
+            }
+        }
+    }
+}
+
+// presented tag normalization, applied before tag is checked or sent upstream
+pub struct IdTagPolicy {
+    pub case: IdTagCase,
+    pub hex: bool, // tag is hexadecimal, separators and 0x prefix are removed
+    pub formats: Vec<IdTagFormat>, // empty accepts any format
+}
+
+impl IdTagPolicy {
+    pub fn normalize(&self, tagid: &str) -> Result<String, AfbError> {
+        // card sectors are NUL or space padded
+        let mut idtag = tagid
+            .trim_matches(|char: char| char == '\0' || char.is_whitespace())
+            .to_string();
+        if idtag.is_empty() {
+            return afb_error!("idtag-invalid", "empty tagid");
+        }
+
+        if self.hex {
+            let digits = idtag
+                .strip_prefix("0x")
+                .or_else(|| idtag.strip_prefix("0X"))
+                .unwrap_or(&idtag);
+            let digits: String = digits
+                .chars()
+                .filter(|digit| !matches!(digit, ':' | '-' | ' '))
+                .collect();
+            if digits.is_empty() || !digits.chars().all(|digit| digit.is_ascii_hexdigit()) {
+                return afb_error!("idtag-invalid", "tagid is not hexadecimal");
+            }
+            idtag = digits;
+        }
+
+        idtag = match self.case {
+            IdTagCase::Keep => idtag,
+            IdTagCase::Upper => idtag.to_uppercase(),
+            IdTagCase::Lower => idtag.to_lowercase(),
+        };
+
+        if !self.formats.is_empty() {
+            idtag = match self.formats.iter().find_map(|format| format.check(&idtag)) {
+                Some(idtag) => idtag,
+                None => {
+                    return afb_error!(
+                        "idtag-invalid",
+                        "tagid matches none of {:?} formats",
+                        self.formats
+                    )
+                }
+            };
+        }
+
+        if idtag.chars().any(|char| char.is_control()) {
+            return afb_error!("idtag-invalid", "tagid contains control characters");
+        }
+        if idtag.chars().count() > IDTAG_MAX_LEN {
+            return afb_error!(
+                "idtag-invalid",
+                "tagid exceeds OCPP idTag {} characters",
+                IDTAG_MAX_LEN
+            );
+        }
+        Ok(idtag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(case: IdTagCase, hex: bool, formats: Vec<IdTagFormat>) -> IdTagPolicy {
+        IdTagPolicy { case, hex, formats }
+    }
+
+    #[test]
+    fn uid_idtag() {
+        assert_eq!(uid_to_idtag("04a2b3c4", false).unwrap(), "04A2B3C4");
+        assert_eq!(uid_to_idtag("04:a2:b3:c4\0\0", false).unwrap(), "04A2B3C4");
+        assert_eq!(uid_to_idtag("04-A2-B3-C4", true).unwrap(), "C4B3A204");
+    }
+
+    #[test]
+    fn uid_idtag_invalid() {
+        assert!(uid_to_idtag("", false).is_err());
+        assert!(uid_to_idtag("\0\0", false).is_err());
+        assert!(uid_to_idtag("04a2b3c", false).is_err());
+        assert!(uid_to_idtag("04a2b3zz", false).is_err());
+        // 11 bytes do not fit a 20 characters idTag
+        assert!(uid_to_idtag("0102030405060708090a0b", false).is_err());
+    }
+
+    #[test]
+    fn normalize_padding_and_case() {
+        let keep = policy(IdTagCase::Keep, false, Vec::new());
+        assert_eq!(keep.normalize("  Tag-01\0\0").unwrap(), "Tag-01");
+        let upper = policy(IdTagCase::Upper, false, Vec::new());
+        assert_eq!(upper.normalize("tag-01").unwrap(), "TAG-01");
+        let lower = policy(IdTagCase::Lower, false, Vec::new());
+        assert_eq!(lower.normalize("TAG-01").unwrap(), "tag-01");
+    }
+
+    #[test]
+    fn normalize_hex() {
+        let hex = policy(IdTagCase::Upper, true, Vec::new());
+        assert_eq!(hex.normalize("0x04:a2:b3:c4").unwrap(), "04A2B3C4");
+        assert_eq!(hex.normalize("04-a2-b3-c4").unwrap(), "04A2B3C4");
+        assert!(hex.normalize("0x").is_err());
+        assert!(hex.normalize("04a2g3").is_err());
+    }
+
+    #[test]
+    fn normalize_refused() {
+        let keep = policy(IdTagCase::Keep, false, Vec::new());
+        assert!(keep.normalize("").is_err());
+        assert!(keep.normalize(" \0 ").is_err());
+        assert!(keep.normalize("tag\x01id").is_err());
+        assert!(keep.normalize("123456789012345678901").is_err());
+        assert!(keep.normalize("12345678901234567890").is_ok());
+    }
+
+    #[test]
+    fn normalize_formats() {
+        let iso = policy(IdTagCase::Upper, true, vec![IdTagFormat::Iso14443]);
+        assert_eq!(iso.normalize("04a2b3c4").unwrap(), "04A2B3C4");
+        assert_eq!(iso.normalize("04a2b3c4d5e6f7").unwrap(), "04A2B3C4D5E6F7");
+        assert!(iso.normalize("04a2b3").is_err());
+
+        let emaid = policy(IdTagCase::Upper, false, vec![IdTagFormat::Emaid]);
+        assert_eq!(
+            emaid.normalize("fr-ioT-c12345678").unwrap(),
+            "FRIOTC12345678"
+        );
+        assert_eq!(
+            emaid.normalize("FR-IOT-C12345678-5").unwrap(),
+            "FRIOTC123456785"
+        );
+        assert!(emaid.normalize("12IOTC12345678").is_err());
+        assert!(emaid.normalize("FR-IOT-C1234").is_err());
+
+        let any = policy(
+            IdTagCase::Upper,
+            false,
+            vec![IdTagFormat::Iso14443, IdTagFormat::Emaid],
+        );
+        assert!(any.normalize("04A2B3C4").is_ok());
+        assert!(any.normalize("FRIOTC12345678").is_ok());
+    }
+
+    #[test]
+    fn parse_config() {
+        assert!(IdTagCase::parse("UPPER").unwrap() == IdTagCase::Upper);
+        assert!(IdTagCase::parse("title").is_err());
+        assert_eq!(IdTagFormat::parse("emaid").unwrap(), IdTagFormat::Emaid);
+        assert!(IdTagFormat::parse("iso15693").is_err());
+    }
+}
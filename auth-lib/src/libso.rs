@@ -18,9 +18,48 @@
 #[cfg(not(afbv4))]
 extern crate afbv4;
 
+#[path = "backend.rs"]
+mod backend;
+
+#[path = "cache.rs"]
+mod cache;
+
+#[path = "fsm.rs"]
+mod fsm;
+
+#[path = "idtag.rs"]
+mod idtag;
+
+#[path = "locallist.rs"]
+mod locallist;
+
+#[path = "lockout.rs"]
+mod lockout;
+
 #[path = "manager.rs"]
 mod manager;
 
+#[path = "privacy.rs"]
+mod privacy;
+
+#[path = "signature.rs"]
+mod signature;
+
+#[path = "store.rs"]
+mod store;
+
+#[path = "uidregistry.rs"]
+mod uidregistry;
+
 pub mod prelude {
+    pub use crate::backend::*;
+    pub use crate::cache::*;
+    pub use crate::fsm::*;
+    pub use crate::idtag::*;
+    pub use crate::locallist::*;
+    pub use crate::lockout::*;
     pub use crate::manager::*;
-}
\ No newline at end of file
+    pub use crate::privacy::*;
+    pub use crate::signature::*;
+    pub use crate::uidregistry::*;
+}
@@ -0,0 +1,294 @@
+/*
+ * Copyright (C) 2015-2022 IoT.bzh Company
+ * Author: Fulup Ar Foll <fulup@iot.bzh>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ */
+
+use crate::store::write_atomic;
+use afbv4::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+use typesv4::prelude::*;
+
+// OCPP-1.6 Local Authorization List, version 0 is an empty list
+pub struct LocalAuthList {
+    version: i32,
+    entries: HashMap<String, OcppIdTagInfo>,
+    path: Option<&'static str>,
+    pre_authorize: bool,
+}
+
+impl LocalAuthList {
+    pub fn new(path: Option<&'static str>, pre_authorize: bool) -> Result<Self, AfbError> {
+        let mut local_list = LocalAuthList {
+            version: 0,
+            entries: HashMap::new(),
+            path,
+            pre_authorize,
+        };
+
+        // reload previously stored list if any
+        if let Some(path) = path {
+            match fs::read_to_string(path) {
+                Ok(data) => {
+                    let stored: OcppLocalList = match serde_json::from_str(&data) {
+                        Ok(value) => value,
+                        Err(error) => {
+                            return afb_error!(
+                                "local-list-load",
+                                "invalid local list file:{} error:{}",
+                                path,
+                                error
+                            )
+                        }
+                    };
+                    local_list.version = stored.version;
+                    local_list.apply(&stored.list);
+                }
+                Err(error) => {
+                    afb_log_msg!(
+                        Notice,
+                        None,
+                        "local list file:{} not loaded ({})",
+                        path,
+                        error
+                    );
+                }
+            }
+        }
+        Ok(local_list)
+    }
+
+    pub fn get_version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn get(&self, tagid: &str) -> Option<&OcppIdTagInfo> {
+        self.entries.get(tagid)
+    }
+
+    // LocalPreAuthorize: valid tags from local list start without central system request
+    pub fn pre_authorize(&self, tagid: &str, now: Duration) -> Option<&OcppIdTagInfo> {
+        if !self.pre_authorize {
+            return None;
+        }
+        self.entries.get(tagid).filter(|info| info.is_valid(now))
+    }
+
+    // add/update entries with idtag_info, remove entries without
+    fn apply(&mut self, list: &[OcppAuthData]) {
+        for entry in list {
+            match &entry.idtag_info {
+                Some(info) => {
+                    self.entries.insert(entry.idtag.clone(), info.clone());
+                }
+                None => {
+                    self.entries.remove(&entry.idtag);
+                }
+            }
+        }
+    }
+
+    // SendLocalList semantic
+    pub fn update(&mut self, request: &OcppLocalList) -> Result<OcppUpdateStatus, AfbError> {
+        if request.version <= 0 {
+            return Ok(OcppUpdateStatus::Failed);
+        }
+
+        match request.update {
+            OcppUpdateType::Full => {
+                if request.list.iter().any(|entry| entry.idtag_info.is_none()) {
+                    return Ok(OcppUpdateStatus::Failed);
+                }
+                self.entries.clear();
+            }
+            OcppUpdateType::Differential => {
+                if request.version <= self.version {
+                    return Ok(OcppUpdateStatus::VersionMismatch);
+                }
+            }
+        }
+
+        self.apply(&request.list);
+        self.version = request.version;
+        self.store()?;
+        Ok(OcppUpdateStatus::Accepted)
+    }
+
+    fn store(&self) -> Result<(), AfbError> {
+        let path = match self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let snapshot = OcppLocalList {
+            version: self.version,
+            update: OcppUpdateType::Full,
+            list: self
+                .entries
+                .iter()
+                .map(|(idtag, info)| OcppAuthData {
+                    idtag: idtag.clone(),
+                    idtag_info: Some(info.clone()),
+                })
+                .collect(),
+        };
+
+        let data = match serde_json::to_string(&snapshot) {
+            Ok(value) => value,
+            Err(error) => return afb_error!("local-list-store", "serialize error:{}", error),
+        };
+
+        if let Err(error) = write_atomic(path, &data) {
+            return afb_error!(
+                "local-list-store",
+                "fail to write file:{} error:{}",
+                path,
+                error
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(idtag: &str, status: Option<OcppAuthStatus>) -> OcppAuthData {
+        OcppAuthData {
+            idtag: idtag.to_string(),
+            idtag_info: status.map(|status| OcppIdTagInfo {
+                status,
+                expiry_date: Some(Duration::from_secs(100)),
+                parent_idtag: None,
+            }),
+        }
+    }
+
+    fn request(version: i32, update: OcppUpdateType, list: Vec<OcppAuthData>) -> OcppLocalList {
+        OcppLocalList {
+            version,
+            update,
+            list,
+        }
+    }
+
+    #[test]
+    fn full_update() {
+        let mut local_list = LocalAuthList::new(None, true).unwrap();
+        assert_eq!(local_list.get_version(), 0);
+
+        let full = request(
+            3,
+            OcppUpdateType::Full,
+            vec![
+                entry("tag-a", Some(OcppAuthStatus::Accepted)),
+                entry("tag-b", Some(OcppAuthStatus::Blocked)),
+            ],
+        );
+        assert_eq!(
+            local_list.update(&full).unwrap(),
+            OcppUpdateStatus::Accepted
+        );
+        assert_eq!(local_list.get_version(), 3);
+        assert!(local_list.get("tag-b").is_some());
+
+        // full update replaces the whole list
+        let full = request(
+            4,
+            OcppUpdateType::Full,
+            vec![entry("tag-c", Some(OcppAuthStatus::Accepted))],
+        );
+        assert_eq!(
+            local_list.update(&full).unwrap(),
+            OcppUpdateStatus::Accepted
+        );
+        assert!(local_list.get("tag-a").is_none());
+        assert!(local_list.get("tag-c").is_some());
+    }
+
+    #[test]
+    fn differential_update() {
+        let mut local_list = LocalAuthList::new(None, true).unwrap();
+        let full = request(
+            2,
+            OcppUpdateType::Full,
+            vec![entry("tag-a", Some(OcppAuthStatus::Accepted))],
+        );
+        local_list.update(&full).unwrap();
+
+        let stale = request(2, OcppUpdateType::Differential, Vec::new());
+        assert_eq!(
+            local_list.update(&stale).unwrap(),
+            OcppUpdateStatus::VersionMismatch
+        );
+
+        let diff = request(
+            3,
+            OcppUpdateType::Differential,
+            vec![
+                entry("tag-a", None),
+                entry("tag-b", Some(OcppAuthStatus::Accepted)),
+            ],
+        );
+        assert_eq!(
+            local_list.update(&diff).unwrap(),
+            OcppUpdateStatus::Accepted
+        );
+        assert!(local_list.get("tag-a").is_none());
+        assert!(local_list.get("tag-b").is_some());
+    }
+
+    #[test]
+    fn invalid_update() {
+        let mut local_list = LocalAuthList::new(None, true).unwrap();
+        let zero = request(0, OcppUpdateType::Full, Vec::new());
+        assert_eq!(local_list.update(&zero).unwrap(), OcppUpdateStatus::Failed);
+
+        // full update cannot remove entries
+        let full = request(1, OcppUpdateType::Full, vec![entry("tag-a", None)]);
+        assert_eq!(local_list.update(&full).unwrap(), OcppUpdateStatus::Failed);
+        assert_eq!(local_list.get_version(), 0);
+    }
+
+    #[test]
+    fn pre_authorize() {
+        let mut local_list = LocalAuthList::new(None, true).unwrap();
+        let full = request(
+            1,
+            OcppUpdateType::Full,
+            vec![
+                entry("tag-a", Some(OcppAuthStatus::Accepted)),
+                entry("tag-b", Some(OcppAuthStatus::Blocked)),
+            ],
+        );
+        local_list.update(&full).unwrap();
+
+        assert!(local_list
+            .pre_authorize("tag-a", Duration::from_secs(50))
+            .is_some());
+        assert!(local_list
+            .pre_authorize("tag-a", Duration::from_secs(100))
+            .is_none());
+        assert!(local_list
+            .pre_authorize("tag-b", Duration::from_secs(50))
+            .is_none());
+        assert!(local_list
+            .pre_authorize("tag-c", Duration::from_secs(50))
+            .is_none());
+
+        let mut disabled = LocalAuthList::new(None, false).unwrap();
+        disabled.update(&full).unwrap();
+        assert!(disabled
+            .pre_authorize("tag-a", Duration::from_secs(50))
+            .is_none());
+    }
+}
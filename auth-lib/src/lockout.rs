@@ -0,0 +1,323 @@
+/*
+ * Copyright (C) 2015-2022 IoT.bzh Company
+ * Author: Fulup Ar Foll <fulup@iot.bzh>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ */
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use typesv4::prelude::*;
+
+// lockout doubles at each level, capped by policy max
+const LOCKOUT_LEVEL_MAX: u32 = 16;
+
+struct LockoutCounter {
+    failures: VecDeque<Duration>,
+    level: u32,
+    until: Duration,
+}
+
+impl LockoutCounter {
+    fn new() -> Self {
+        LockoutCounter {
+            failures: VecDeque::new(),
+            level: 0,
+            until: Duration::ZERO,
+        }
+    }
+
+    // forget failures out of sliding window, level decays after a clean window past lockout end
+    fn purge(&mut self, now: Duration, window: Duration) {
+        while let Some(failure) = self.failures.front() {
+            if now.saturating_sub(*failure) < window {
+                break;
+            }
+            self.failures.pop_front();
+        }
+        if self.failures.is_empty() && now >= self.until + window {
+            self.level = 0;
+        }
+    }
+
+    fn retry_after(&self, now: Duration) -> Option<Duration> {
+        if self.until > now {
+            Some(self.until - now)
+        } else {
+            None
+        }
+    }
+
+    // exponential lockout once threshold is reached within window
+    fn lock(
+        &mut self,
+        threshold: u32,
+        base: Duration,
+        max: Duration,
+        now: Duration,
+    ) -> Option<AuthLockoutEvent> {
+        self.failures.push_back(now);
+        if (self.failures.len() as u32) < threshold {
+            return None;
+        }
+
+        let delay = base
+            .saturating_mul(1 << self.level.min(LOCKOUT_LEVEL_MAX))
+            .min(max);
+        let failures = self.failures.len() as u32;
+        self.failures.clear();
+        self.level += 1;
+        self.until = now + delay;
+
+        Some(AuthLockoutEvent {
+            scope: AuthLockoutScope::Global,
+            tagid: None,
+            failures,
+            level: self.level,
+            retry_after: delay.as_secs() as u32,
+        })
+    }
+
+    fn is_idle(&self) -> bool {
+        self.failures.is_empty() && self.level == 0
+    }
+
+    fn dump(&self, tagid: Option<String>, now: Duration) -> AuthLockoutCounter {
+        AuthLockoutCounter {
+            tagid,
+            failures: self.failures.len() as u32,
+            level: self.level,
+            retry_after: self
+                .retry_after(now)
+                .map_or(0, |delay| delay.as_secs() as u32),
+        }
+    }
+}
+
+// failed login counters per tagid and for the whole charger, a zero threshold disables the scope
+pub struct AuthLockout {
+    window: Duration,
+    tag_threshold: u32,
+    global_threshold: u32,
+    base: Duration,
+    max: Duration,
+    global: LockoutCounter,
+    tags: HashMap<String, LockoutCounter>,
+}
+
+impl AuthLockout {
+    pub fn new(
+        window: Duration,
+        tag_threshold: u32,
+        global_threshold: u32,
+        base: Duration,
+        max: Duration,
+    ) -> Self {
+        AuthLockout {
+            window,
+            tag_threshold,
+            global_threshold,
+            base,
+            max,
+            global: LockoutCounter::new(),
+            tags: HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.tag_threshold > 0 || self.global_threshold > 0
+    }
+
+    // remaining global lockout, checked before reading any card
+    pub fn check_global(&mut self, now: Duration) -> Option<Duration> {
+        self.global.purge(now, self.window);
+        self.global.retry_after(now)
+    }
+
+    // remaining lockout of presented tagid
+    pub fn check_tag(&mut self, tagid: &str, now: Duration) -> Option<Duration> {
+        let counter = self.tags.get_mut(tagid)?;
+        counter.purge(now, self.window);
+        counter.retry_after(now)
+    }
+
+    // record failed login, returns lockout events for tag and/or global scope
+    pub fn failure(&mut self, tagid: &str, now: Duration) -> Vec<AuthLockoutEvent> {
+        let mut events = Vec::new();
+        if !self.is_enabled() {
+            return events;
+        }
+
+        // unknown tags should not grow the table forever
+        let window = self.window;
+        self.tags.retain(|_, counter| {
+            counter.purge(now, window);
+            !counter.is_idle()
+        });
+
+        if self.tag_threshold > 0 && !tagid.is_empty() {
+            let counter = self
+                .tags
+                .entry(tagid.to_string())
+                .or_insert_with(LockoutCounter::new);
+            if let Some(event) = counter.lock(self.tag_threshold, self.base, self.max, now) {
+                events.push(AuthLockoutEvent {
+                    scope: AuthLockoutScope::Tag,
+                    tagid: Some(tagid.to_string()),
+                    ..event
+                });
+            }
+        }
+
+        if self.global_threshold > 0 {
+            self.global.purge(now, self.window);
+            if let Some(event) = self
+                .global
+                .lock(self.global_threshold, self.base, self.max, now)
+            {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    // successful login clears tag history, global counter only decays with time
+    pub fn success(&mut self, tagid: &str) {
+        self.tags.remove(tagid);
+    }
+
+    pub fn reset(&mut self) {
+        self.global = LockoutCounter::new();
+        self.tags.clear();
+    }
+
+    pub fn dump(&self, now: Duration) -> AuthLockoutDump {
+        AuthLockoutDump {
+            enabled: self.is_enabled(),
+            window: self.window.as_secs() as u32,
+            global: self.global.dump(None, now),
+            tags: self
+                .tags
+                .iter()
+                .map(|(tagid, counter)| counter.dump(Some(tagid.clone()), now))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    // 3 failures within 60s, 10s lockout doubling up to 40s
+    fn tag_lockout() -> AuthLockout {
+        AuthLockout::new(secs(60), 3, 0, secs(10), secs(40))
+    }
+
+    #[test]
+    fn disabled() {
+        let mut lockout = AuthLockout::new(secs(60), 0, 0, secs(10), secs(40));
+        assert!(!lockout.is_enabled());
+        for now in 0..10 {
+            assert!(lockout.failure("tag-a", secs(now)).is_empty());
+        }
+        assert_eq!(lockout.check_tag("tag-a", secs(10)), None);
+        assert_eq!(lockout.check_global(secs(10)), None);
+    }
+
+    #[test]
+    fn tag_threshold() {
+        let mut lockout = tag_lockout();
+        assert!(lockout.failure("tag-a", secs(0)).is_empty());
+        assert!(lockout.failure("tag-a", secs(1)).is_empty());
+
+        let events = lockout.failure("tag-a", secs(2));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].scope, AuthLockoutScope::Tag);
+        assert_eq!(events[0].tagid.as_deref(), Some("tag-a"));
+        assert_eq!(events[0].failures, 3);
+        assert_eq!(events[0].level, 1);
+        assert_eq!(events[0].retry_after, 10);
+
+        assert_eq!(lockout.check_tag("tag-a", secs(5)), Some(secs(7)));
+        assert_eq!(lockout.check_tag("tag-a", secs(12)), None);
+        assert_eq!(lockout.check_tag("tag-b", secs(5)), None);
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let mut lockout = tag_lockout();
+        let mut now = 0;
+        let mut delays = Vec::new();
+        for _ in 0..4 {
+            let mut events = Vec::new();
+            for _ in 0..3 {
+                events = lockout.failure("tag-a", secs(now));
+                now += 1;
+            }
+            delays.push(events[0].retry_after);
+            // next attempts once lockout is over, within decay window
+            now += events[0].retry_after as u64;
+        }
+        assert_eq!(delays, vec![10, 20, 40, 40]);
+    }
+
+    #[test]
+    fn window_expiry() {
+        let mut lockout = tag_lockout();
+        assert!(lockout.failure("tag-a", secs(0)).is_empty());
+        assert!(lockout.failure("tag-a", secs(1)).is_empty());
+        // first failures left the sliding window
+        assert!(lockout.failure("tag-a", secs(70)).is_empty());
+        assert!(lockout.failure("tag-a", secs(71)).is_empty());
+        assert_eq!(lockout.failure("tag-a", secs(72)).len(), 1);
+    }
+
+    #[test]
+    fn level_decay() {
+        let mut lockout = tag_lockout();
+        for now in 0..3 {
+            lockout.failure("tag-a", secs(now));
+        }
+        // lockout ended at 12s, a clean window later level is back to base delay
+        let events: Vec<_> = (100..103)
+            .flat_map(|now| lockout.failure("tag-a", secs(now)))
+            .collect();
+        assert_eq!(events[0].level, 1);
+        assert_eq!(events[0].retry_after, 10);
+    }
+
+    #[test]
+    fn success_clears_tag() {
+        let mut lockout = tag_lockout();
+        lockout.failure("tag-a", secs(0));
+        lockout.failure("tag-a", secs(1));
+        lockout.success("tag-a");
+        assert!(lockout.failure("tag-a", secs(2)).is_empty());
+        assert_eq!(lockout.dump(secs(2)).tags[0].failures, 1);
+    }
+
+    #[test]
+    fn global_threshold() {
+        let mut lockout = AuthLockout::new(secs(60), 0, 3, secs(10), secs(40));
+        assert!(lockout.failure("tag-a", secs(0)).is_empty());
+        assert!(lockout.failure("tag-b", secs(1)).is_empty());
+        let events = lockout.failure("", secs(2));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].scope, AuthLockoutScope::Global);
+        assert_eq!(events[0].tagid, None);
+        assert_eq!(lockout.check_global(secs(3)), Some(secs(9)));
+
+        lockout.reset();
+        assert_eq!(lockout.check_global(secs(3)), None);
+    }
+}
@@ -10,31 +10,182 @@
  *
  */
 
+use crate::prelude::*;
 use afbv4::prelude::*;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use typesv4::prelude::*;
 
+fn get_now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::new(0, 0))
+}
+
+// energy budget (Wh) used up caps the session to zero, a zero budget is unlimited
+fn budget_limit(limit: AuthLimit, emax: u32, delivered: u32) -> AuthLimit {
+    if emax == 0 || delivered < emax {
+        return limit;
+    }
+    AuthLimit {
+        imax: 0,
+        pmax: 0,
+        source: AuthLimitSource::Budget,
+    }
+}
+
+// only a presented tag refused by policy counts as a failed attempt. Unreachable backends,
+// unread cards and attempts on a locked out badge do not extend lockout
+fn lockout_counted(data_set: &AuthState) -> bool {
+    !data_set.tagid.is_empty()
+        && matches!(
+            data_set.reason,
+            AuthReason::Blocked
+                | AuthReason::Expired
+                | AuthReason::Invalid
+                | AuthReason::TagidInvalid
+                | AuthReason::ContractInvalid
+                | AuthReason::ContractExpired
+                | AuthReason::ContractWindow
+                | AuthReason::SignatureInvalid
+                | AuthReason::UidMismatch
+        )
+}
+
+// free-vend skips identification and authorization, ocpp still accounts the transaction
+fn free_vend_stage(policy: &FreeVendPolicy, data_set: &mut AuthState) -> AuthStage {
+    match &policy.idtag {
+        Some(idtag) => {
+            data_set.tagid = idtag.clone();
+            data_set.ocpp_check = true;
+            data_set.decision = AuthDecision::FreeVend;
+            AuthStage::Start
+        }
+        None => AuthStage::Identify,
+    }
+}
+
+// OCPP LocalAuthorizeOffline/AllowOfflineTxForUnknownId behavior when backends are unreachable
+pub struct OfflinePolicy {
+    pub local_authorize: bool,
+    pub allow_unknown: bool,
+    pub unknown_emax: u32, // energy budget (Wh) granted to unknown tags, 0 for unlimited
+}
+
+// when badge is set, only the session tag, its parentIdTag group or a master badge may stop the session
+pub struct StopPolicy {
+    pub badge: bool,
+    pub masters: Vec<String>,
+}
+
+// card presentation deadline, a zero timeout reads the card only once
+pub struct LoginPolicy {
+    pub timeout: Duration,
+    pub poll: Duration,
+}
+
+// session defaults when badge has no contract, site limits also cap contract limits
+pub struct DefaultPolicy {
+    pub imax: u32,        // site current limit (A)
+    pub pmax: u32,        // site power limit (kW)
+    pub ocpp_check: bool, // OCPP authorization mandatory, when false contracts may still require it
+}
+
+// session limits pushed to energy manager, site limits are restored at logout
+pub struct EnergyPolicy {
+    pub api: Option<&'static str>, // None when limits are left to energy manager
+    pub verb: &'static str,
+}
+
+// free-vend sites authorize every login without badge, transactions are
+// still opened under a configured idTag for energy accounting
+pub struct FreeVendPolicy {
+    pub idtag: Option<String>, // None when free-vend is disabled
+}
+
+// authentication policies from binding config
+pub struct AuthPolicy {
+    pub defaults: DefaultPolicy,
+    pub offline: OfflinePolicy,
+    pub stop: StopPolicy,
+    pub login: LoginPolicy,
+    pub signature: SignaturePolicy,
+    pub privacy: PrivacyPolicy,
+    pub energy: EnergyPolicy,
+    pub idtag: IdTagPolicy,
+    pub free_vend: FreeVendPolicy,
+}
+
+// msg pushes AuthMsg, state pushes AuthState with reason, security pushes AuthSecurity alerts,
+// lockout pushes AuthLockoutEvent, limit pushes effective AuthLimit
+pub struct AuthEvents {
+    pub msg: &'static AfbEvent,
+    pub state: &'static AfbEvent,
+    pub security: &'static AfbEvent,
+    pub lockout: &'static AfbEvent,
+    pub limit: &'static AfbEvent,
+}
+
+// OCPP smart charging limit, tid 0 applies to any transaction
+struct OcppLimit {
+    id: u32,
+    tid: i32,
+    imax: u32,
+}
+
 pub struct ManagerHandle {
     data_set: Mutex<AuthState>,
     event: &'static AfbEvent,
-    scard_api: &'static str,
-    ocpp_api: Option<&'static str>,
-    engy_api: &'static str,
+    state_event: &'static AfbEvent,
+    security_event: &'static AfbEvent,
+    lockout_event: &'static AfbEvent,
+    limit_event: &'static AfbEvent,
+    backends: Vec<Box<dyn AuthBackend>>,
+    local_list: Option<Mutex<LocalAuthList>>,
+    uid_registry: Option<Mutex<UidRegistry>>,
+    cache: Mutex<AuthCache>,
+    lockout: Mutex<AuthLockout>,
+    policy: AuthPolicy,
+    flow: Mutex<Option<LoginFlow>>,
+    flow_count: AtomicU32,
+    cancel: AtomicBool,
+    ocpp_limit: Mutex<Option<OcppLimit>>,
+    ocpp_limit_count: AtomicU32,
+    limit: Mutex<Option<AuthLimit>>, // last limit pushed to energy manager
+    delivered: AtomicU32,            // session energy (Wh) from energy manager
 }
 
 impl ManagerHandle {
     pub fn new(
-        event: &'static AfbEvent,
-        scard_api: &'static str,
-        ocpp_api: Option<&'static str>,
-        engy_api: &'static str,
+        events: AuthEvents,
+        backends: Vec<Box<dyn AuthBackend>>,
+        local_list: Option<LocalAuthList>,
+        uid_registry: Option<UidRegistry>,
+        cache: AuthCache,
+        lockout: AuthLockout,
+        policy: AuthPolicy,
     ) -> &'static mut Self {
         let handle = ManagerHandle {
             data_set: Mutex::new(AuthState::default()),
-            event,
-            scard_api,
-            ocpp_api,
-            engy_api,
+            event: events.msg,
+            state_event: events.state,
+            security_event: events.security,
+            lockout_event: events.lockout,
+            limit_event: events.limit,
+            backends,
+            local_list: local_list.map(Mutex::new),
+            uid_registry: uid_registry.map(Mutex::new),
+            cache: Mutex::new(cache),
+            lockout: Mutex::new(lockout),
+            policy,
+            flow: Mutex::new(None),
+            flow_count: AtomicU32::new(0),
+            cancel: AtomicBool::new(false),
+            ocpp_limit: Mutex::new(None),
+            ocpp_limit_count: AtomicU32::new(0),
+            limit: Mutex::new(None),
+            delivered: AtomicU32::new(0),
         };
 
         // return a static handle to prevent Rust from complaining when moving/sharing it
@@ -47,187 +198,1443 @@ impl ManagerHandle {
         Ok(guard)
     }
 
+    // session state with personal data masked by privacy policy
+    pub fn get_public_state(&self) -> Result<AuthState, AfbError> {
+        let data_set = self.get_state()?;
+        Ok(self.policy.privacy.public(&data_set))
+    }
+
+    // GetLocalListVersion: -1 when local list is not enabled
+    pub fn get_local_list_version(&self) -> i32 {
+        match &self.local_list {
+            Some(local_list) => local_list.lock().unwrap().get_version(),
+            None => -1,
+        }
+    }
+
+    // SendLocalList from ocpp binding
+    pub fn send_local_list(&self, request: &OcppLocalList) -> Result<OcppUpdateStatus, AfbError> {
+        let status = match &self.local_list {
+            Some(local_list) => local_list.lock().unwrap().update(request)?,
+            None => OcppUpdateStatus::NotSupported,
+        };
+        afb_log_msg!(
+            Notice,
+            self.event,
+            "local list update:{:?} version:{} status:{:?}",
+            request.update,
+            request.version,
+            status
+        );
+        Ok(status)
+    }
+
+    fn local_pre_authorize(&self, tagid: &str, now: Duration) -> Option<OcppIdTagInfo> {
+        match &self.local_list {
+            Some(local_list) => local_list
+                .lock()
+                .unwrap()
+                .pre_authorize(tagid, now)
+                .cloned(),
+            None => None,
+        }
+    }
+
+    fn local_list_get(&self, tagid: &str) -> Option<OcppIdTagInfo> {
+        match &self.local_list {
+            Some(local_list) => local_list.lock().unwrap().get(tagid).cloned(),
+            None => None,
+        }
+    }
+
+    pub fn cache_dump(&self) -> AuthCacheDump {
+        self.cache.lock().unwrap().dump()
+    }
+
+    pub fn cache_clear(&self) {
+        afb_log_msg!(Notice, self.event, "authorization cache cleared");
+        self.cache.lock().unwrap().clear();
+    }
+
+    pub fn lockout_dump(&self) -> AuthLockoutDump {
+        self.lockout.lock().unwrap().dump(get_now())
+    }
+
+    pub fn lockout_reset(&self) {
+        afb_log_msg!(Notice, self.event, "login lockout counters cleared");
+        self.lockout.lock().unwrap().reset();
+    }
+
+    // count failed login, publish lockout when a threshold is reached
+    fn lockout_failure(&self, data_set: &AuthState) {
+        let events = self
+            .lockout
+            .lock()
+            .unwrap()
+            .failure(&data_set.tagid, get_now());
+        for mut event in events {
+            afb_log_msg!(
+                Warning,
+                self.event,
+                "login lockout scope:{:?} failures:{} retry-after:{}s",
+                event.scope,
+                event.failures,
+                event.retry_after
+            );
+            event.tagid = event.tagid.map(|tagid| self.policy.privacy.tag(&tagid));
+            self.lockout_event.push(event);
+        }
+    }
+
+    // malformed tags are refused before reaching lockout, registry or OCPP
+    fn check_idtag(&self, data_set: &mut AuthState) -> Result<(), AfbError> {
+        match self.policy.idtag.normalize(&data_set.tagid) {
+            Ok(idtag) => {
+                data_set.tagid = idtag;
+                Ok(())
+            }
+            Err(error) => {
+                data_set.reason = AuthReason::TagidFormat;
+                data_set.tagid = String::new();
+                Err(error)
+            }
+        }
+    }
+
+    // presented tagid still locked out, login is refused without contacting backends
+    fn check_lockout(&self, data_set: &mut AuthState, now: Duration) -> Result<(), AfbError> {
+        let retry_after = match self.lockout.lock().unwrap().check_tag(&data_set.tagid, now) {
+            Some(retry_after) => retry_after,
+            None => return Ok(()),
+        };
+        data_set.reason = AuthReason::Locked;
+        data_set.retry_after = retry_after.as_secs() as u32;
+        afb_error!(
+            "auth-login-locked",
+            "badge locked out, retry after {}s",
+            data_set.retry_after
+        )
+    }
+
+    fn cache_authorize(&self, tagid: &str, now: Duration) -> Option<OcppIdTagInfo> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(tagid, now)
+            .filter(|idtag_info| idtag_info.is_valid(now))
+    }
+
+    fn cache_update(&self, tagid: &str, idtag_info: OcppIdTagInfo, now: Duration) {
+        self.cache.lock().unwrap().update(tagid, idtag_info, now);
+    }
+
+    // session energy is checked against emax budget before being reported to backends
     pub fn update_engy_state(&self, engy_state: EnergyState) -> Result<(), AfbError> {
-        if self.ocpp_api.is_some() {
-            AfbSubCall::call_sync(
-                self.event.get_apiv4(),
-                self.ocpp_api.unwrap(),
-                "push-measure",
-                engy_state,
-            )?;
+        self.delivered
+            .store(engy_state.session.max(0) as u32, Ordering::SeqCst);
+        self.limit_refresh()?;
+
+        // meter values only make sense within an opened ocpp transaction
+        let tid = self.data_set.lock().unwrap().tid;
+        if tid == 0 {
+            return Ok(());
+        }
+        for backend in &self.backends {
+            backend.update_engy_state(self.event.get_apiv4(), &engy_state)?;
         }
         Ok(())
     }
 
-    pub fn logout(&self, energy_session: i32) -> Result<AuthState, AfbError> {
-        let mut data_set = self.get_state()?;
-        match data_set.auth {
-            AuthMsg::Done => {} // session is active let's logout
-            AuthMsg::Fail => {}
-            _ => {
+    // push session limits to energy manager
+    fn engy_limit(&self, imax: u32, pmax: u32) -> Result<(), AfbError> {
+        let api = match self.policy.energy.api {
+            Some(api) => api,
+            None => return Ok(()),
+        };
+        let limits = EngyConfSet {
+            imax: imax as i32,
+            pmax: pmax as i32,
+        };
+        AfbSubCall::call_sync(self.event.get_apiv4(), api, self.policy.energy.verb, limits)?;
+        afb_log_msg!(
+            Notice,
+            self.event,
+            "energy limits imax:{}A pmax:{}kW",
+            imax,
+            pmax
+        );
+        Ok(())
+    }
+
+    fn site_limit(&self) -> AuthLimit {
+        AuthLimit {
+            imax: self.policy.defaults.imax,
+            pmax: self.policy.defaults.pmax,
+            source: AuthLimitSource::Site,
+        }
+    }
+
+    // effective session current is the lowest of site, contract and ocpp smart charging limits
+    fn session_limit(&self, data_set: &AuthState) -> AuthLimit {
+        let mut limit = self.site_limit();
+        if let Some(contract) = &data_set.contract {
+            if contract.imax < limit.imax {
+                limit.imax = contract.imax;
+                limit.source = AuthLimitSource::Contract;
+            }
+            limit.pmax = limit.pmax.min(contract.pmax);
+        }
+        if let Some(ocpp) = &*self.ocpp_limit.lock().unwrap() {
+            if (ocpp.tid == 0 || ocpp.tid == data_set.tid) && ocpp.imax < limit.imax {
+                limit.imax = ocpp.imax;
+                limit.source = AuthLimitSource::Ocpp;
+            }
+        }
+        budget_limit(limit, data_set.emax, self.delivered.load(Ordering::SeqCst))
+    }
+
+    // push limit to energy manager and publish it when it changes
+    fn limit_apply(&self, limit: AuthLimit) {
+        let mut current = self.limit.lock().unwrap();
+        if *current == Some(limit) {
+            return;
+        }
+        match self.engy_limit(limit.imax, limit.pmax) {
+            Ok(()) => {
+                *current = Some(limit);
+                self.limit_event.push(limit);
+            }
+            Err(error) => afb_log_msg!(Error, self.event, "fail to apply energy limits: {}", error),
+        }
+    }
+
+    // recompute active session limits after an ocpp limit change,
+    // energy manager subcall runs without holding session state
+    fn limit_refresh(&self) -> Result<(), AfbError> {
+        let limit = {
+            let mut data_set = self.get_state()?;
+            if data_set.auth != AuthMsg::Done {
+                return Ok(());
+            }
+            let limit = self.session_limit(&data_set);
+            data_set.imax = limit.imax;
+            data_set.pmax = limit.pmax;
+            limit
+        };
+        self.limit_apply(limit);
+        Ok(())
+    }
+
+    // OCPP PowerLimit from SetChargingProfile, a negative imax clears it, duration 0 never expires.
+    // Site wide limits (tid 0) are kept while idle, other transactions limits are ignored
+    pub fn ocpp_power_limit(&'static self, power_limit: &PowerLimit) -> Result<(), AfbError> {
+        let tid = self.get_state()?.tid;
+        if power_limit.tid != 0 && power_limit.tid != tid {
+            afb_log_msg!(
+                Debug,
+                self.event,
+                "ocpp power limit ignored tid:{} session tid:{}",
+                power_limit.tid,
+                tid
+            );
+            return Ok(());
+        }
+        let id = self.ocpp_limit_count.fetch_add(1, Ordering::SeqCst);
+        afb_log_msg!(
+            Notice,
+            self.event,
+            "ocpp power limit tid:{} imax:{}A duration:{}s",
+            power_limit.tid,
+            power_limit.imax,
+            power_limit.duration
+        );
+
+        if power_limit.imax < 0 {
+            *self.ocpp_limit.lock().unwrap() = None;
+        } else {
+            *self.ocpp_limit.lock().unwrap() = Some(OcppLimit {
+                id,
+                tid: power_limit.tid,
+                imax: power_limit.imax as u32,
+            });
+            if power_limit.duration > 0 {
+                AfbTimer::new("ocpp-limit")
+                    .set_period(power_limit.duration.saturating_mul(1000))
+                    .set_decount(1)
+                    .set_callback(ocpp_limit_cb)
+                    .set_context(LimitCtx { mgr: self, id })
+                    .start()?;
+            }
+        }
+        self.limit_refresh()
+    }
+
+    // expiry timer of a replaced limit is ignored
+    fn ocpp_limit_expire(&self, id: u32) -> Result<(), AfbError> {
+        {
+            let mut ocpp_limit = self.ocpp_limit.lock().unwrap();
+            match &*ocpp_limit {
+                Some(limit) if limit.id == id => *ocpp_limit = None,
+                _ => return Ok(()),
+            }
+        }
+        afb_log_msg!(Notice, self.event, "ocpp power limit expired");
+        self.limit_refresh()
+    }
+
+    // badge provisioning requires the registry and an idle reader
+    fn provision_registry(&self) -> Result<&Mutex<UidRegistry>, AfbError> {
+        if self.get_state()?.auth == AuthMsg::Pending {
+            return afb_error!("auth-provision-busy", "reader busy with a pending login");
+        }
+        match &self.uid_registry {
+            Some(registry) => Ok(registry),
+            None => afb_error!(
+                "auth-provision-config",
+                "uid_binding registry not configured"
+            ),
+        }
+    }
+
+    fn scard_call(
+        &self,
+        scard: &ScardVerbs,
+        verb: &'static str,
+        arg: AuthSubcallArg,
+    ) -> Result<AfbRqtData, AfbError> {
+        AuthSubcall::new(scard.api, verb, arg).call_sync(self.event.get_apiv4())
+    }
+
+    fn scard_read(&self, scard: &ScardVerbs, verb: &'static str) -> Result<String, AfbError> {
+        self.scard_call(scard, verb, AuthSubcallArg::Flag(true))?
+            .get::<String>(0)
+    }
+
+    // sign contract, write every configured sector and read them back
+    fn write_contract(
+        &self,
+        scard: &ScardVerbs,
+        tagid: &str,
+        uid: &str,
+        contract: &mut Contract,
+    ) -> Result<(), AfbError> {
+        if let Err(error) = contract.validate() {
+            return afb_error!("auth-provision-contract", "{}", error);
+        }
+
+        if self.policy.signature.is_enabled() {
+            self.policy.signature.sign(contract, tagid, uid)?;
+        }
+
+        let sectors = match contract.encode_sectors(CONTRACT_SECTOR_LEN) {
+            Ok(sectors) => sectors,
+            Err(error) => return afb_error!("auth-provision-contract", "{}", error),
+        };
+        if sectors.len() > scard.contract.sectors.len() {
+            return afb_error!(
+                "auth-provision-contract",
+                "contract needs {} sectors, {} configured",
+                sectors.len(),
+                scard.contract.sectors.len()
+            );
+        }
+
+        // unused sectors are zeroed, stale data would otherwise be decoded
+        for (idx, verbs) in scard.contract.sectors.iter().enumerate() {
+            let sector = sectors
+                .get(idx)
+                .cloned()
+                .unwrap_or_else(|| vec![0; CONTRACT_SECTOR_LEN]);
+            let jsector = match serde_json::to_string(&sector) {
+                Ok(value) => JsoncObj::parse(&value)?,
+                Err(error) => return afb_error!("auth-provision-contract", "{}", error),
+            };
+            self.scard_call(scard, verbs.set, AuthSubcallArg::Json(jsector))?;
+        }
+
+        let mut readback = Vec::new();
+        for verbs in &scard.contract.sectors {
+            let response = self.scard_call(scard, verbs.get, AuthSubcallArg::Flag(true))?;
+            readback.extend(scard_bytes(&response.get::<JsoncObj>(0)?));
+        }
+
+        match Contract::decode(&readback) {
+            Ok(stored) if stored == *contract => Ok(()),
+            Ok(_) => afb_error!(
+                "auth-provision-verify",
+                "contract read back differs from written one"
+            ),
+            Err(error) => afb_error!("auth-provision-verify", "contract read back {}", error),
+        }
+    }
+
+    // write tagid, name and signed contract on presented card, reissuing a tagid
+    // binds it to the new card so the previous one is refused
+    pub fn issue_badge(
+        &self,
+        scard: &ScardVerbs,
+        request: &AuthBadgeRequest,
+    ) -> Result<AuthBadge, AfbError> {
+        let registry = self.provision_registry()?;
+        // badge is signed and registered with the tagid login will see
+        let tagid = self.policy.idtag.normalize(&request.tagid)?;
+        let uid = self.scard_read(scard, scard.uid.get())?;
+
+        self.scard_call(scard, scard.tag.set(), AuthSubcallArg::Text(tagid.clone()))?;
+        if let Some(name) = &request.name {
+            self.scard_call(scard, scard.name.set(), AuthSubcallArg::Text(name.clone()))?;
+        }
+
+        let mut contract = request.contract.clone();
+        self.write_contract(scard, &tagid, &uid, &mut contract)?;
+
+        let readback = self.scard_read(scard, scard.tag.get())?;
+        if self.policy.idtag.normalize(&readback).ok().as_ref() != Some(&tagid) {
+            return afb_error!(
+                "auth-provision-verify",
+                "tagid read back:{} expected:{}",
+                self.policy.privacy.tag(&readback),
+                self.policy.privacy.tag(&tagid)
+            );
+        }
+
+        if let Some(name) = &request.name {
+            let readback = self.scard_read(scard, scard.name.get())?;
+            if readback.trim() != name.trim() {
+                return afb_error!(
+                    "auth-provision-verify",
+                    "holder name read back does not match, card sector too short?"
+                );
+            }
+        }
+
+        let badge = AuthBadge {
+            tagid,
+            uid,
+            name: request.name.clone(),
+            issued: get_now(),
+            revoked: false,
+        };
+        registry.lock().unwrap().update(badge.clone())?;
+        afb_log_msg!(
+            Notice,
+            self.event,
+            "badge issued tagid:{} uid:{}",
+            self.policy.privacy.tag(&badge.tagid),
+            self.policy.privacy.tag(&badge.uid)
+        );
+        Ok(badge)
+    }
+
+    // rewrite contract of presented registered card
+    pub fn update_contract(
+        &self,
+        scard: &ScardVerbs,
+        contract: &Contract,
+    ) -> Result<AuthBadge, AfbError> {
+        let registry = self.provision_registry()?;
+        let tagid = self.scard_read(scard, scard.tag.get())?;
+        let tagid = &self.policy.idtag.normalize(&tagid)?;
+        let uid = self.scard_read(scard, scard.uid.get())?;
+
+        let badge = match registry.lock().unwrap().get(tagid) {
+            Some(badge) if !badge.revoked && badge.uid.eq_ignore_ascii_case(&uid) => badge.clone(),
+            Some(_) => {
+                return afb_error!(
+                    "auth-provision-refused",
+                    "tagid:{} revoked or bound to another card",
+                    self.policy.privacy.tag(tagid)
+                )
+            }
+            None => {
+                return afb_error!(
+                    "auth-provision-refused",
+                    "tagid:{} not registered",
+                    self.policy.privacy.tag(tagid)
+                )
+            }
+        };
+
+        let mut contract = contract.clone();
+        self.write_contract(scard, &badge.tagid, &badge.uid, &mut contract)?;
+        afb_log_msg!(
+            Notice,
+            self.event,
+            "badge contract updated tagid:{}",
+            self.policy.privacy.tag(tagid)
+        );
+        Ok(badge)
+    }
+
+    // revoked badges are refused at login, even with a valid signed contract
+    pub fn revoke_badge(&self, tagid: &str) -> Result<AuthBadge, AfbError> {
+        let tagid = &self.policy.idtag.normalize(tagid)?;
+        let badge = self.provision_registry()?.lock().unwrap().revoke(tagid)?;
+        afb_log_msg!(
+            Notice,
+            self.event,
+            "badge revoked tagid:{}",
+            self.policy.privacy.tag(tagid)
+        );
+        Ok(badge)
+    }
+
+    // run backend stage with synchronous subcalls, None when backend ignores the stage
+    fn backend_sync(
+        &self,
+        backend: &dyn AuthBackend,
+        stage: AuthStage,
+        data_set: &mut AuthState,
+    ) -> Result<Option<AuthStep>, AfbError> {
+        let mut step = 0;
+        while let Some(subcall) = backend.get_subcall(stage, step, data_set) {
+            let status = match subcall.call_sync(self.event.get_apiv4()) {
+                Ok(response) => backend.on_response(stage, step, data_set, &response)?,
+                Err(error) => backend.on_error(stage, step, data_set, error)?,
+            };
+            match status {
+                AuthStep::Next => step += 1,
+                status => return Ok(Some(status)),
+            }
+        }
+        Ok(None)
+    }
+
+    // retrieve presented tag group from local list, cache or authorization backends
+    fn get_parent_idtag(&self, badge: &mut AuthState, now: Duration) -> Option<String> {
+        if let Some(idtag_info) = self.local_list_get(&badge.tagid) {
+            return idtag_info.parent_idtag;
+        }
+
+        if let Some(idtag_info) = self.cache.lock().unwrap().get(&badge.tagid, now) {
+            return idtag_info.parent_idtag;
+        }
+
+        for backend in &self.backends {
+            if let Ok(Some(AuthStep::Authorized(idtag_info))) =
+                self.backend_sync(backend.as_ref(), AuthStage::Authorize, badge)
+            {
+                self.cache_update(&badge.tagid, idtag_info.clone(), now);
+                return idtag_info.parent_idtag;
+            }
+        }
+        None
+    }
+
+    // first backend that accepts provides tagid and contract
+    fn identify(&self, data_set: &mut AuthState) -> Result<(), AfbError> {
+        for backend in &self.backends {
+            match self.backend_sync(backend.as_ref(), AuthStage::Identify, data_set)? {
+                Some(AuthStep::Done(AuthBackendStatus::Accepted)) => return Ok(()),
+                Some(AuthStep::Done(AuthBackendStatus::Refused)) => {
+                    return afb_error!(
+                        "auth-login-fail",
+                        "identification refused by backend:{}",
+                        backend.get_uid()
+                    )
+                }
+                _ => {}
+            }
+        }
+        afb_error!("auth-login-fail", "no backend could identify user")
+    }
+
+    // read presented badge and check it is allowed to stop current session
+    fn check_stop_badge(&self, data_set: &AuthState) -> Result<(), AfbError> {
+        let mut badge = AuthState::default();
+        badge.ocpp_check = self.policy.defaults.ocpp_check;
+        self.identify(&mut badge)?;
+        self.check_idtag(&mut badge)?;
+        // cloned, revoked or tampered badges cannot stop a session, even as master
+        self.check_uid(&mut badge)?;
+        self.check_signature(&mut badge)?;
+        if let Some(contract) = &badge.contract {
+            badge.ocpp_check = self.ocpp_check(contract);
+        }
+
+        let allowed = if badge.tagid == data_set.tagid {
+            true
+        } else if self.policy.stop.masters.contains(&badge.tagid) {
+            afb_log_msg!(Notice, self.event, "session stopped by master badge");
+            true
+        } else {
+            match (
+                &data_set.parent_idtag,
+                self.get_parent_idtag(&mut badge, get_now()),
+            ) {
+                (Some(session_group), Some(badge_group)) => *session_group == badge_group,
+                _ => false,
+            }
+        };
+
+        if !allowed {
+            let mut refused = data_set.clone();
+            refused.reason = AuthReason::StopRefused;
+            self.state_event.push(self.policy.privacy.public(&refused));
+            return afb_error!(
+                "auth-logout-refused",
+                "presented badge does not match session tagid or group"
+            );
+        }
+        Ok(())
+    }
+
+    // transaction id comes from the session unless explicitly overloaded by caller,
+    // card read and stop subcalls run without holding session state
+    pub fn logout(&self, energy_session: Option<i32>) -> Result<AuthState, AfbError> {
+        let session = self.get_state()?.clone();
+        // session is active, presented badge should be allowed to stop it
+        let badge_check = session.auth == AuthMsg::Done
+            && self.policy.stop.badge
+            && session.decision != AuthDecision::FreeVend;
+        if badge_check {
+            self.check_stop_badge(&session)?;
+        }
+
+        let mut stopped = {
+            let mut data_set = self.get_state()?;
+            if badge_check && (data_set.auth != session.auth || data_set.tagid != session.tagid) {
                 return afb_error!(
-                    "auth-logout-fail",
-                    "current session not authenticate status:{:?}",
-                    data_set.auth
+                    "auth-logout-refused",
+                    "session changed while checking stop badge"
+                );
+            }
+            self.transition(&mut data_set, AuthTrigger::Logout)?;
+
+            data_set.tagid = String::new();
+            data_set.imax = 0;
+            data_set.pmax = 0;
+            data_set.emax = 0;
+            data_set.decision = AuthDecision::Unset;
+            data_set.parent_idtag = None;
+            data_set.contract = None;
+            data_set.uid = String::new();
+            data_set.name = None;
+            let mut stopped = data_set.clone();
+            stopped.tid = energy_session.unwrap_or(data_set.tid);
+            data_set.tid = 0;
+            stopped
+        };
+        self.event.push(stopped.auth);
+
+        // session is already reset, a failing backend should not leave session limits in place
+        for backend in &self.backends {
+            if let Err(error) = self.backend_sync(backend.as_ref(), AuthStage::Stop, &mut stopped) {
+                afb_log_msg!(
+                    Error,
+                    self.event,
+                    "backend:{} stop failed: {}",
+                    backend.get_uid(),
+                    error
+                );
+            }
+        }
+
+        // transaction profile ends with its transaction
+        {
+            let mut ocpp_limit = self.ocpp_limit.lock().unwrap();
+            if ocpp_limit.as_ref().is_some_and(|limit| limit.tid != 0) {
+                *ocpp_limit = None;
+            }
+        }
+        stopped.tid = 0;
+        self.limit_apply(self.site_limit());
+
+        Ok(stopped)
+    }
+
+    // abort a pending login, a flow waiting for card is completed immediately,
+    // a flow with a card read in flight is aborted as soon as the read returns
+    pub fn cancel(&self) -> Result<(), AfbError> {
+        let parked = {
+            let mut flow = self.flow.lock().unwrap();
+            match &*flow {
+                Some(parked) if parked.stage != AuthStage::Identify => {
+                    return afb_error!("auth-cancel-fail", "pending login already authorizing")
+                }
+                _ => flow.take(),
+            }
+        };
+        afb_log_msg!(Notice, self.event, "pending login cancel requested");
+
+        match parked {
+            Some(mut flow) => {
+                flow.data_set.reason = AuthReason::Cancelled;
+                self.login_complete(
+                    flow,
+                    afb_error!("auth-login-cancel", "pending login cancelled"),
                 );
             }
+            None => {
+                if self.get_state()?.auth != AuthMsg::Pending {
+                    return afb_error!("auth-cancel-fail", "no pending login to cancel");
+                }
+                self.cancel.store(true, Ordering::SeqCst);
+            }
         }
+        Ok(())
+    }
 
+    // check transition against state machine table, log it and update session state
+    fn transition(
+        &self,
+        data_set: &mut AuthState,
+        trigger: AuthTrigger,
+    ) -> Result<AuthMsg, AfbError> {
+        let from = data_set.auth;
+        let to = auth_transition(from, trigger)?;
+        afb_log_msg!(
+            Notice,
+            self.event,
+            "auth transition {:?} -> {:?} cause:{:?}",
+            from,
+            to,
+            trigger
+        );
+        data_set.auth = to;
+        Ok(to)
+    }
+
+    // timeout/cancel return to idle, state event carries the reason
+    fn login_abort(&self, data_set: &mut AuthState) {
+        data_set.tagid = String::new();
+        self.event.push(data_set.auth);
+        self.state_event.push(self.policy.privacy.public(data_set));
+    }
+
+    // reset session and notify failure, state event carries the failure reason
+    fn login_fail(&self, data_set: &mut AuthState) {
         data_set.tagid = String::new();
-        data_set.auth = AuthMsg::Idle;
-        data_set.imax = 0;
-        data_set.pmax = 0;
+        if data_set.reason == AuthReason::None {
+            data_set.reason = AuthReason::Refused;
+        }
         self.event.push(data_set.auth);
+        self.state_event.push(self.policy.privacy.public(data_set));
+    }
+
+    fn security_alert(&self, data_set: &AuthState, error: &AfbError) {
+        afb_log_msg!(Warning, self.event, "security alert: {}", error);
+        self.security_event.push(AuthSecurity {
+            tagid: self.policy.privacy.tag(&data_set.tagid),
+            uid: self.policy.privacy.tag(&data_set.uid),
+            reason: data_set.reason,
+            info: error.to_string(),
+        });
+    }
+
+    // check presented card hardware uid against tagid binding
+    fn check_uid(&self, data_set: &mut AuthState) -> Result<(), AfbError> {
+        let registry = match &self.uid_registry {
+            Some(registry) => registry,
+            None => return Ok(()),
+        };
+
+        // signed contract binding is verified with contract signature
+        let signed = self.policy.signature.is_enabled()
+            && data_set
+                .contract
+                .as_ref()
+                .is_some_and(|contract| contract.signature.is_some());
+
+        let registry = registry.lock().unwrap();
+        if let Err(error) = registry.check(&data_set.tagid, &data_set.uid, signed) {
+            data_set.reason = match registry.get(&data_set.tagid) {
+                Some(badge) if badge.revoked => AuthReason::Blocked,
+                _ => AuthReason::UidMismatch,
+            };
+            self.security_alert(data_set, &error);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // tampered or cloned card
+    fn check_signature(&self, data_set: &mut AuthState) -> Result<(), AfbError> {
+        if let Err(error) = self.policy.signature.verify(data_set) {
+            data_set.reason = AuthReason::SignatureInvalid;
+            self.security_alert(data_set, &error);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // apply badge contract limits, malformed, expired or out of window contracts are refused
+    fn check_contract(&self, data_set: &mut AuthState, now: Duration) -> Result<(), AfbError> {
+        self.check_signature(data_set)?;
+
+        let contract = match data_set.contract.clone() {
+            Some(contract) => contract,
+            None => return Ok(()),
+        };
+
+        if let Err(error) = contract.check(now) {
+            data_set.reason = match error {
+                ContractError::Expired => AuthReason::ContractExpired,
+                ContractError::OutsideWindow => AuthReason::ContractWindow,
+                _ => AuthReason::ContractInvalid,
+            };
+            return afb_error!("auth-contract-refused", "{}", error);
+        }
+
+        data_set.imax = contract.imax;
+        data_set.pmax = contract.pmax;
+        data_set.ocpp_check = self.ocpp_check(&contract);
+        data_set.emax = contract.emax;
+        Ok(())
+    }
+
+    // card contract cannot waive a site mandatory OCPP check
+    fn ocpp_check(&self, contract: &Contract) -> bool {
+        self.policy.defaults.ocpp_check || contract.ocpp
+    }
+
+    // local list/cache may authorize the tagid without any backend round trip
+    fn pre_authorize(&self, data_set: &mut AuthState, now: Duration) -> bool {
+        if let Some(idtag_info) = self.local_pre_authorize(&data_set.tagid, now) {
+            afb_log_msg!(Notice, self.event, "tagid pre-authorized from local list");
+            data_set.decision = AuthDecision::LocalList;
+            data_set.parent_idtag = idtag_info.parent_idtag;
+            return true;
+        }
+
+        if let Some(idtag_info) = self.cache_authorize(&data_set.tagid, now) {
+            afb_log_msg!(Notice, self.event, "tagid pre-authorized from cache");
+            data_set.decision = AuthDecision::Cache;
+            data_set.parent_idtag = idtag_info.parent_idtag;
+            return true;
+        }
+        false
+    }
 
-        if data_set.ocpp_check && self.ocpp_api.is_some() {
-            AfbSubCall::call_sync(
-                self.event.get_apiv4(),
-                self.engy_api,
-                "state",
-                EnergyAction::UNSUBSCRIBE,
-            )?;
+    // accepted tag should not be expired, any other status is a refusal with its own reason
+    fn check_idtag_info(
+        &self,
+        data_set: &mut AuthState,
+        idtag_info: &OcppIdTagInfo,
+        now: Duration,
+    ) -> Result<(), AfbError> {
+        let status = match idtag_info.status {
+            OcppAuthStatus::Accepted if !idtag_info.is_valid(now) => OcppAuthStatus::Expired,
+            status => status,
+        };
 
-            AfbSubCall::call_sync(
-                self.event.get_apiv4(),
-                self.ocpp_api.unwrap(),
-                "transaction",
-                OcppTransaction::Stop(energy_session),
-            )?;
+        if status != OcppAuthStatus::Accepted {
+            data_set.reason = AuthReason::from_status(status);
+            return afb_error!(
+                "auth-login-fail",
+                "authentication refused status:{:?}",
+                status
+            );
+        }
 
-            AfbSubCall::call_sync(
-                self.event.get_apiv4(),
-                self.ocpp_api.unwrap(),
-                "status-notification",
-                OcppChargerStatus::Available,
-            )?;
+        if idtag_info.parent_idtag.is_some() {
+            data_set.parent_idtag = idtag_info.parent_idtag.clone();
         }
+        Ok(())
+    }
 
-        Ok(data_set.clone())
+    // authorization backend is unreachable, apply offline policy
+    fn offline_authorize(
+        &self,
+        data_set: &mut AuthState,
+        error: AfbError,
+        now: Duration,
+    ) -> Result<(), AfbError> {
+        afb_log_msg!(Notice, self.event, "authorization offline: {}", error);
+
+        if self.policy.offline.local_authorize {
+            let known = match self.local_list_get(&data_set.tagid) {
+                Some(idtag_info) => Some(idtag_info),
+                None => self.cache.lock().unwrap().get(&data_set.tagid, now),
+            };
+
+            if let Some(idtag_info) = known {
+                if let Err(error) = self.check_idtag_info(data_set, &idtag_info, now) {
+                    data_set.decision = AuthDecision::OfflineRefused;
+                    return Err(error);
+                }
+                afb_log_msg!(
+                    Notice,
+                    self.event,
+                    "offline tagid accepted from local list/cache"
+                );
+                data_set.decision = AuthDecision::OfflineLocal;
+                return Ok(());
+            }
+        }
+
+        if self.policy.offline.allow_unknown {
+            afb_log_msg!(
+                Notice,
+                self.event,
+                "offline unknown tagid accepted energy budget:{}Wh",
+                self.policy.offline.unknown_emax
+            );
+            data_set.decision = AuthDecision::OfflineUnknown;
+            data_set.emax = self.policy.offline.unknown_emax;
+            return Ok(());
+        }
+
+        data_set.decision = AuthDecision::OfflineRefused;
+        data_set.reason = AuthReason::Offline;
+        Err(error)
     }
 
-    pub fn login(&self) -> Result<AuthState, AfbError> {
+    // login reply is sent when the asynchronous flow completes
+    pub fn login(&'static self, rqt: &AfbRequest) -> Result<(), AfbError> {
         let mut data_set = self.get_state()?;
-        match data_set.auth {
-            AuthMsg::Done => {
-                // we're already logged-in let handle SSO
+
+        // too many failed logins, refuse without reading card
+        if data_set.auth != AuthMsg::Done {
+            if let Some(retry_after) = self.lockout.lock().unwrap().check_global(get_now()) {
                 afb_log_msg!(
                     Notice,
                     self.event,
-                    "Session Single Sign On: tagid:{}",
-                    data_set.tagid
+                    "login locked out, retry after {}s",
+                    retry_after.as_secs()
                 );
-                self.event.push(data_set.auth);
-                return Ok(data_set.clone());
+                let mut refused = self.policy.privacy.public(&data_set);
+                refused.reason = AuthReason::Locked;
+                refused.retry_after = retry_after.as_secs() as u32;
+                rqt.reply(refused, -1);
+                return Ok(());
             }
-            _ => {}
+        }
+
+        if self.transition(&mut data_set, AuthTrigger::Login)? == AuthMsg::Done {
+            // we're already logged-in let handle SSO
+            afb_log_msg!(
+                Notice,
+                self.event,
+                "Session Single Sign On: tagid:{}",
+                self.policy.privacy.tag(&data_set.tagid)
+            );
+            self.event.push(data_set.auth);
+            rqt.reply(self.policy.privacy.public(&data_set), 0);
+            return Ok(());
         }
 
         self.event.push(AuthMsg::Pending);
-        let check_tagid = || -> Result<String, AfbError> {
-            let response =
-                AfbSubCall::call_sync(self.event.get_apiv4(), self.scard_api, "get-tagid", true)?;
-            response.get::<String>(0)
+        data_set.tagid = String::new();
+        data_set.imax = self.policy.defaults.imax;
+        data_set.pmax = self.policy.defaults.pmax;
+        data_set.ocpp_check = self.policy.defaults.ocpp_check;
+        data_set.decision = AuthDecision::Unset;
+        data_set.emax = 0;
+        data_set.reason = AuthReason::None;
+        data_set.parent_idtag = None;
+        data_set.contract = None;
+        data_set.uid = String::new();
+        data_set.name = None;
+        data_set.retry_after = 0;
+        data_set.tid = 0;
+        self.delivered.store(0, Ordering::SeqCst);
+
+        let stage = free_vend_stage(&self.policy.free_vend, &mut data_set);
+        if stage == AuthStage::Start {
+            afb_log_msg!(Notice, self.event, "free-vend session authorized");
+        }
+
+        // flow works on its own copy, shared state is only updated when login completes
+        let mut flow = LoginFlow {
+            id: self.flow_count.fetch_add(1, Ordering::SeqCst),
+            rqt: rqt.add_ref(),
+            data_set: data_set.clone(),
+            stage,
+            backend: 0,
+            step: 0,
+            call: 0,
+            deadline: Instant::now() + self.policy.login.timeout,
+            now: get_now(),
         };
+        drop(data_set);
 
-        let check_contract = || -> Result<JsoncObj, AfbError> {
-            let response = AfbSubCall::call_sync(
-                self.event.get_apiv4(),
-                self.scard_api,
-                "get-contract",
-                true,
-            )?;
-            response.get::<JsoncObj>(0)
+        // a blocked reader never answers, deadline completes a flow still identifying
+        if !self.policy.login.timeout.is_zero() {
+            let timer = AfbTimer::new("login-timeout")
+                .set_period(self.policy.login.timeout.as_millis() as u32)
+                .set_decount(1)
+                .set_callback(login_timeout_cb)
+                .set_context(LoginCtx {
+                    mgr: self,
+                    id: flow.id,
+                    call: flow.call,
+                })
+                .start();
+            if let Err(error) = timer {
+                afb_log_msg!(Error, self.event, "fail to arm login deadline: {}", error);
+            }
+        }
+
+        self.cancel.store(false, Ordering::SeqCst);
+        let next = self.login_next(&mut flow);
+        self.login_dispatch(flow, next);
+        Ok(())
+    }
+
+    // remove parked flow, stale callbacks from a completed, cancelled or resumed flow get None
+    fn login_take(&self, id: u32, call: u32) -> Option<LoginFlow> {
+        let mut flow = self.flow.lock().unwrap();
+        match &*flow {
+            Some(parked) if parked.id == id && parked.call == call => flow.take(),
+            _ => None,
+        }
+    }
+
+    fn login_goto(&self, flow: &mut LoginFlow, stage: AuthStage) {
+        flow.stage = stage;
+        flow.backend = 0;
+        flow.step = 0;
+    }
+
+    // no card read yet, retry until card presentation deadline
+    fn login_retry(&self, flow: &mut LoginFlow, error: AfbError) -> Result<LoginNext, AfbError> {
+        if self.policy.login.timeout.is_zero() {
+            return Err(error);
+        }
+
+        if Instant::now() >= flow.deadline {
+            flow.data_set.reason = AuthReason::Timeout;
+            return afb_error!(
+                "auth-login-timeout",
+                "no card presented within {}ms",
+                self.policy.login.timeout.as_millis()
+            );
+        }
+
+        flow.data_set.reason = AuthReason::None;
+        self.login_goto(flow, AuthStage::Identify);
+        Ok(LoginNext::Poll)
+    }
+
+    // apply current backend outcome, then move to next backend or stage
+    fn login_outcome(
+        &self,
+        flow: &mut LoginFlow,
+        outcome: Result<Option<AuthStep>, AfbError>,
+    ) -> Result<Option<LoginNext>, AfbError> {
+        let outcome = match outcome {
+            Ok(outcome) => outcome,
+            Err(error) => match flow.stage {
+                AuthStage::Identify => return self.login_retry(flow, error).map(Some),
+                AuthStage::Authorize => {
+                    self.offline_authorize(&mut flow.data_set, error, flow.now)?;
+                    self.login_goto(flow, AuthStage::Start);
+                    return Ok(None);
+                }
+                // offline backends are expected to fail, free-vend charges even when ocpp is down
+                _ => match flow.data_set.decision {
+                    AuthDecision::OfflineLocal
+                    | AuthDecision::OfflineUnknown
+                    | AuthDecision::FreeVend => {
+                        afb_log_msg!(Notice, self.event, "offline transaction: {}", error);
+                        None
+                    }
+                    _ => return Err(error),
+                },
+            },
         };
 
-        match check_tagid() {
-            Err(error) => {
-                self.event.push(AuthMsg::Fail);
-                afb_log_msg!(Notice, self.event, "{}", error);
-                data_set.tagid = String::new();
-                data_set.auth = AuthMsg::Fail;
+        match (flow.stage, outcome) {
+            (AuthStage::Identify, Some(AuthStep::Done(AuthBackendStatus::Accepted))) => {
+                flow.now = get_now();
+                self.check_idtag(&mut flow.data_set)?;
+                self.check_lockout(&mut flow.data_set, flow.now)?;
+                self.check_uid(&mut flow.data_set)?;
+                self.check_contract(&mut flow.data_set, flow.now)?;
+                if self.pre_authorize(&mut flow.data_set, flow.now) {
+                    self.login_goto(flow, AuthStage::Start);
+                } else {
+                    self.login_goto(flow, AuthStage::Authorize);
+                }
+                return Ok(None);
+            }
+            (AuthStage::Identify, Some(AuthStep::Done(AuthBackendStatus::Refused))) => {
                 return afb_error!(
                     "auth-login-fail",
-                    "invalid nfc tagid authentication refused"
+                    "identification refused by backend:{}",
+                    self.backends[flow.backend].get_uid()
                 );
             }
-            Ok(nfc_data) => {
-                data_set.tagid = nfc_data;
-                data_set.imax = 32;
-                data_set.pmax = 22;
-                data_set.ocpp_check = true;
+            (AuthStage::Authorize, Some(AuthStep::Authorized(idtag_info))) => {
+                // only cache tags effectively checked by an authorization backend
+                self.cache_update(&flow.data_set.tagid, idtag_info.clone(), flow.now);
+                self.check_idtag_info(&mut flow.data_set, &idtag_info, flow.now)?;
+                flow.data_set.decision = AuthDecision::Online;
             }
+            _ => {}
         }
+        flow.backend += 1;
+        flow.step = 0;
+        Ok(None)
+    }
+
+    // walk backends and stages until next subcall, card poll or completion
+    fn login_next(&self, flow: &mut LoginFlow) -> Result<LoginNext, AfbError> {
+        loop {
+            if flow.stage == AuthStage::Identify && self.cancel.swap(false, Ordering::SeqCst) {
+                flow.data_set.reason = AuthReason::Cancelled;
+                return afb_error!("auth-login-cancel", "pending login cancelled");
+            }
 
-        match check_contract() {
+            if flow.backend >= self.backends.len() {
+                match flow.stage {
+                    AuthStage::Identify => {
+                        return afb_error!("auth-login-fail", "no backend could identify user")
+                    }
+                    AuthStage::Authorize => {
+                        // no backend authorization required by card contract
+                        if flow.data_set.decision == AuthDecision::Unset {
+                            flow.data_set.decision = AuthDecision::Contract;
+                        }
+                        self.login_goto(flow, AuthStage::Start);
+                        continue;
+                    }
+                    _ => return Ok(LoginNext::Complete),
+                }
+            }
+
+            let backend = &self.backends[flow.backend];
+            match backend.get_subcall(flow.stage, flow.step, &flow.data_set) {
+                Some(subcall) => return Ok(LoginNext::Subcall(subcall)),
+                None => {
+                    if let Some(next) = self.login_outcome(flow, Ok(None))? {
+                        return Ok(next);
+                    }
+                }
+            }
+        }
+    }
+
+    // subcall response for current backend step
+    fn login_resume(&'static self, mut flow: LoginFlow, response: Result<&AfbRqtData, AfbError>) {
+        let backend = &self.backends[flow.backend];
+        let outcome = match response {
+            Ok(response) => {
+                backend.on_response(flow.stage, flow.step, &mut flow.data_set, response)
+            }
+            Err(error) => backend.on_error(flow.stage, flow.step, &mut flow.data_set, error),
+        };
+
+        let next = match outcome {
+            Ok(AuthStep::Next) => {
+                flow.step += 1;
+                Ok(None)
+            }
+            Ok(status) => self.login_outcome(&mut flow, Ok(Some(status))),
+            Err(error) => self.login_outcome(&mut flow, Err(error)),
+        };
+
+        let next = match next {
+            Ok(Some(next)) => Ok(next),
+            Ok(None) => self.login_next(&mut flow),
+            Err(error) => Err(error),
+        };
+        self.login_dispatch(flow, next);
+    }
+
+    // park flow while waiting for subcall response or poll timer, state lock is not held
+    fn login_dispatch(&'static self, mut flow: LoginFlow, next: Result<LoginNext, AfbError>) {
+        flow.call += 1;
+        let id = flow.id;
+        let call = flow.call;
+        let stage = flow.stage;
+        let rqt = flow.rqt.add_ref();
+        let status = match next {
+            Ok(LoginNext::Subcall(subcall)) => {
+                *self.flow.lock().unwrap() = Some(flow);
+                let ctx = LoginCtx {
+                    mgr: self,
+                    id,
+                    call,
+                };
+                match subcall.call_async(&rqt, login_response_cb, ctx) {
+                    Ok(()) if stage == AuthStage::Authorize => self.login_stall_arm(id, call),
+                    status => status,
+                }
+            }
+            Ok(LoginNext::Poll) => {
+                *self.flow.lock().unwrap() = Some(flow);
+                AfbTimer::new("login-poll")
+                    .set_period(self.policy.login.poll.as_millis() as u32)
+                    .set_decount(1)
+                    .set_callback(login_poll_cb)
+                    .set_context(LoginCtx {
+                        mgr: self,
+                        id,
+                        call,
+                    })
+                    .start()
+                    .map(|_| ())
+            }
+            Ok(LoginNext::Complete) => {
+                self.login_complete(flow, Ok(()));
+                return;
+            }
             Err(error) => {
-                self.event.push(AuthMsg::Fail);
-                afb_log_msg!(Notice, self.event, "{}", error);
-                data_set.tagid = String::new();
-                data_set.auth = AuthMsg::Fail;
-                return afb_error!("auth-login-fail", "invalid subscription contract");
+                self.login_complete(flow, Err(error));
+                return;
             }
-            Ok(jsonc) => {
-                data_set.imax = jsonc.default::<u32>("imax", 32)?;
-                data_set.pmax = jsonc.default::<u32>("pmax", 22)?;
-                data_set.ocpp_check = jsonc.default::<bool>("ocpp", true)?;
+        };
+
+        if let Err(error) = status {
+            let parked = self.login_take(id, call);
+            if let Some(flow) = parked {
+                self.login_complete(flow, Err(error));
             }
         }
+    }
 
-        // nfc is ok let check occp tag_id
-        if data_set.ocpp_check && self.ocpp_api.is_some() {
-            // Badge with ocpp check
+    // a stalled authorization is handled as ocpp unreachable, transaction start is
+    // always waited for as its transaction id is required to stop it
+    fn login_stall_arm(&'static self, id: u32, call: u32) -> Result<(), AfbError> {
+        if self.policy.login.timeout.is_zero() {
+            return Ok(());
+        }
+        AfbTimer::new("login-authorize-timeout")
+            .set_period(self.policy.login.timeout.as_millis() as u32)
+            .set_decount(1)
+            .set_callback(login_stall_cb)
+            .set_context(LoginCtx {
+                mgr: self,
+                id,
+                call,
+            })
+            .start()?;
+        Ok(())
+    }
 
-            match AfbSubCall::call_sync(
-                self.event.get_apiv4(),
-                self.ocpp_api.unwrap(),
-                "authorize",
-                data_set.tagid.clone(),
-            ) {
-                Ok(response) => {
-                    let ocpp_state = response.get::<bool>(0)?;
-                    if ocpp_state {
-                        data_set.auth = AuthMsg::Done;
-                        afb_log_msg!(Notice, None, "Authentication Done");
-                    } else {
-                        data_set.auth = AuthMsg::Fail;
-                        afb_log_msg!(Notice, None, "Authentication Fail");
-                        self.event.push(data_set.auth);
-                        return afb_error!(
-                            "ocpp-login-fail",
-                            "::::::::NFC rejected by OCPP::::::::"
-                        );
-                    }
+    // commit flow result into shared state and reply to login request
+    fn login_complete(&self, flow: LoginFlow, status: Result<(), AfbError>) {
+        let LoginFlow {
+            rqt, mut data_set, ..
+        } = flow;
+
+        let trigger = match status {
+            Ok(()) => AuthTrigger::Authorized,
+            Err(error) => {
+                afb_log_msg!(Notice, self.event, "{}", error);
+                match data_set.reason {
+                    AuthReason::Timeout => AuthTrigger::Timeout,
+                    AuthReason::Cancelled => AuthTrigger::Cancel,
+                    _ => AuthTrigger::Refused,
                 }
-                Err(_) => {
-                    data_set.auth = AuthMsg::Fail;
-                    self.event.push(data_set.auth);
-                    return afb_error!(
-                        "ocpp-login-fail",
-                        "::::::::OCPP fails to authorize::::::::"
-                    );
+            }
+        };
+
+        // flow only exists while session is pending, every completion trigger is legal
+        if let Err(error) = self.transition(&mut data_set, trigger) {
+            afb_log_msg!(Error, self.event, "{}", error);
+        }
+
+        match trigger {
+            AuthTrigger::Authorized => {
+                afb_log_msg!(Notice, None, "Authentication Done");
+                // reply carries effective limits
+                let limit = self.session_limit(&data_set);
+                data_set.imax = limit.imax;
+                data_set.pmax = limit.pmax;
+                self.limit_apply(limit);
+                self.lockout.lock().unwrap().success(&data_set.tagid);
+                self.event.push(data_set.auth);
+            }
+            AuthTrigger::Refused => {
+                if lockout_counted(&data_set) {
+                    self.lockout_failure(&data_set);
                 }
+                self.login_fail(&mut data_set);
             }
+            AuthTrigger::Timeout | AuthTrigger::Cancel => self.login_abort(&mut data_set),
+            _ => self.login_fail(&mut data_set),
+        }
 
-            // ocpp auth is ok let start ocpp transaction
-            AfbSubCall::call_sync(
-                self.event.get_apiv4(),
-                self.ocpp_api.unwrap(),
-                "transaction",
-                OcppTransaction::Start(data_set.tagid.clone()),
-            )?;
-
-            AfbSubCall::call_sync(
-                self.event.get_apiv4(),
-                self.engy_api,
-                "state",
-                EnergyAction::SUBSCRIBE,
-            )?;
+        let status = if data_set.auth == AuthMsg::Done {
+            0
         } else {
-            // Badge without ocpp check
-            data_set.auth = AuthMsg::Done;
-            afb_log_msg!(Notice, None, "Authentification Done");
+            -1
+        };
+        *self.data_set.lock().unwrap() = data_set.clone();
+        rqt.reply(self.policy.privacy.public(&data_set), status);
+    }
+}
+
+// next action of a pending login
+enum LoginNext {
+    Subcall(AuthSubcall),
+    Poll,
+    Complete,
+}
+
+// pending login, parked in ManagerHandle while a subcall or poll timer is in flight
+struct LoginFlow {
+    id: u32,
+    rqt: AfbRequest,
+    data_set: AuthState,
+    stage: AuthStage,
+    backend: usize,
+    step: usize,
+    call: u32, // parked dispatch count, drops late callbacks of a resumed flow
+    deadline: Instant,
+    now: Duration,
+}
+
+struct LoginCtx {
+    mgr: &'static ManagerHandle,
+    id: u32,
+    call: u32,
+}
+
+fn login_response_cb(
+    _rqt: &AfbRequest,
+    args: &AfbRqtData,
+    ctx: &AfbCtxData,
+) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<LoginCtx>()?;
+    let parked = ctx.mgr.login_take(ctx.id, ctx.call);
+    if let Some(flow) = parked {
+        let response = match args.get_status() {
+            status if status < 0 => afb_error!("auth-subcall-fail", "subcall status:{}", status),
+            _ => Ok(args),
+        };
+        ctx.mgr.login_resume(flow, response);
+    }
+    Ok(())
+}
+
+fn login_poll_cb(_timer: &AfbTimer, _decount: u32, ctx: &AfbCtxData) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<LoginCtx>()?;
+    let parked = ctx.mgr.login_take(ctx.id, ctx.call);
+    if let Some(mut flow) = parked {
+        let next = ctx.mgr.login_next(&mut flow);
+        ctx.mgr.login_dispatch(flow, next);
+    }
+    Ok(())
+}
+
+// card presentation deadline, a flow past identification is never timed out here.
+// Late subcall responses of a timed out flow are ignored by login_take
+fn login_timeout_cb(_timer: &AfbTimer, _decount: u32, ctx: &AfbCtxData) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<LoginCtx>()?;
+    let parked = {
+        let mut flow = ctx.mgr.flow.lock().unwrap();
+        match &*flow {
+            Some(parked) if parked.id == ctx.id && parked.stage == AuthStage::Identify => {
+                flow.take()
+            }
+            _ => None,
         }
-        self.event.push(data_set.auth);
-        Ok(data_set.clone())
+    };
+    if let Some(mut flow) = parked {
+        flow.data_set.reason = AuthReason::Timeout;
+        let error = afb_error!(
+            "auth-login-timeout",
+            "no card identified within {}ms",
+            ctx.mgr.policy.login.timeout.as_millis()
+        );
+        ctx.mgr.login_complete(flow, error);
+    }
+    Ok(())
+}
+
+// ocpp authorize subcall never answered, resume flow with offline authorization
+fn login_stall_cb(_timer: &AfbTimer, _decount: u32, ctx: &AfbCtxData) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<LoginCtx>()?;
+    let parked = ctx.mgr.login_take(ctx.id, ctx.call);
+    if let Some(flow) = parked {
+        let error = afb_error!(
+            "auth-authorize-timeout",
+            "authorization not answered within {}ms",
+            ctx.mgr.policy.login.timeout.as_millis()
+        );
+        ctx.mgr.login_resume(flow, error);
+    }
+    Ok(())
+}
+
+struct LimitCtx {
+    mgr: &'static ManagerHandle,
+    id: u32,
+}
+
+fn ocpp_limit_cb(_timer: &AfbTimer, _decount: u32, ctx: &AfbCtxData) -> Result<(), AfbError> {
+    let ctx = ctx.get_ref::<LimitCtx>()?;
+    ctx.mgr.ocpp_limit_expire(ctx.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site_limit() -> AuthLimit {
+        AuthLimit {
+            imax: 32,
+            pmax: 22,
+            source: AuthLimitSource::Site,
+        }
+    }
+
+    #[test]
+    fn budget_unlimited() {
+        assert_eq!(budget_limit(site_limit(), 0, 100_000), site_limit());
+    }
+
+    #[test]
+    fn budget_below_emax() {
+        assert_eq!(budget_limit(site_limit(), 10_000, 9_999), site_limit());
+    }
+
+    #[test]
+    fn budget_cutoff() {
+        let limit = budget_limit(site_limit(), 10_000, 10_000);
+        assert_eq!(limit.imax, 0);
+        assert_eq!(limit.pmax, 0);
+        assert_eq!(limit.source, AuthLimitSource::Budget);
+        assert_eq!(budget_limit(site_limit(), 10_000, 12_000), limit);
+    }
+
+    #[test]
+    fn lockout_policy_refusal() {
+        let mut data_set = AuthState::default();
+        data_set.tagid = "04A1B2C3".to_string();
+        data_set.reason = AuthReason::Invalid;
+        assert!(lockout_counted(&data_set));
+        data_set.reason = AuthReason::UidMismatch;
+        assert!(lockout_counted(&data_set));
+    }
+
+    #[test]
+    fn lockout_not_counted() {
+        let mut data_set = AuthState::default();
+        data_set.tagid = "04A1B2C3".to_string();
+        for reason in [AuthReason::Offline, AuthReason::Locked, AuthReason::None] {
+            data_set.reason = reason;
+            assert!(!lockout_counted(&data_set));
+        }
+        // card read or identification failure, no tag presented
+        data_set.tagid = String::new();
+        data_set.reason = AuthReason::Invalid;
+        assert!(!lockout_counted(&data_set));
+    }
+
+    #[test]
+    fn free_vend_disabled() {
+        let mut data_set = AuthState::default();
+        let policy = FreeVendPolicy { idtag: None };
+        assert_eq!(free_vend_stage(&policy, &mut data_set), AuthStage::Identify);
+        assert_eq!(data_set.decision, AuthDecision::Unset);
+        assert!(data_set.tagid.is_empty());
+    }
+
+    #[test]
+    fn free_vend_enabled() {
+        let mut data_set = AuthState::default();
+        data_set.ocpp_check = false;
+        let policy = FreeVendPolicy {
+            idtag: Some("FREEVEND".to_string()),
+        };
+        assert_eq!(free_vend_stage(&policy, &mut data_set), AuthStage::Start);
+        assert_eq!(data_set.decision, AuthDecision::FreeVend);
+        assert_eq!(data_set.tagid, "FREEVEND");
+        assert!(data_set.ocpp_check);
     }
 }
@@ -0,0 +1,169 @@
+/*
+ * Copyright (C) 2015-2022 IoT.bzh Company
+ * Author: Fulup Ar Foll <fulup@iot.bzh>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ */
+
+use afbv4::prelude::*;
+use sha2::{Digest, Sha256};
+use typesv4::prelude::*;
+
+// how personal data is rendered on public events and replies
+#[derive(Clone, Copy, PartialEq)]
+pub enum PrivacyMask {
+    Clear,
+    Initials,
+    Truncated,
+    Hash,
+    Hidden,
+}
+
+impl PrivacyMask {
+    pub fn parse(mask: &str) -> Result<Self, AfbError> {
+        let mask = match mask.to_lowercase().as_str() {
+            "clear" => PrivacyMask::Clear,
+            "initials" => PrivacyMask::Initials,
+            "truncated" => PrivacyMask::Truncated,
+            "hash" => PrivacyMask::Hash,
+            "hidden" => PrivacyMask::Hidden,
+            _ => {
+                return afb_error!(
+                    "privacy-config-fail",
+                    "mask:{} should be clear|initials|truncated|hash|hidden",
+                    mask
+                )
+            }
+        };
+        Ok(mask)
+    }
+}
+
+// privileged clients read clear values through admin verbs only,
+// OCPP backend is the only one receiving the clear tagid
+pub struct PrivacyPolicy {
+    pub name: PrivacyMask,
+    pub tagid: PrivacyMask, // applies to tagid, card uid and parentIdTag
+    pub salt: String,       // hash salt, should be unique per charger
+}
+
+impl PrivacyPolicy {
+    // salted sha256, truncated to keep events short while staying correlatable
+    pub fn hash(&self, value: &str) -> String {
+        let mut digest = Sha256::new();
+        digest.update(self.salt.as_bytes());
+        digest.update(value.as_bytes());
+        let mut hash = hex::encode(digest.finalize());
+        hash.truncate(16);
+        hash
+    }
+
+    fn mask(&self, mask: PrivacyMask, value: &str) -> Option<String> {
+        match mask {
+            PrivacyMask::Clear => Some(value.to_string()),
+            PrivacyMask::Initials => Some(
+                value
+                    .split_whitespace()
+                    .filter_map(|word| word.chars().next())
+                    .flat_map(|initial| initial.to_uppercase().chain(Some('.')))
+                    .collect(),
+            ),
+            // keep last 4 digits, enough for a user to recognize its own badge
+            PrivacyMask::Truncated => {
+                let skip = value.chars().count().saturating_sub(4);
+                Some(format!("*{}", value.chars().skip(skip).collect::<String>()))
+            }
+            PrivacyMask::Hash => Some(self.hash(value)),
+            PrivacyMask::Hidden => None,
+        }
+    }
+
+    // tagid or card uid as written in logs and public events
+    pub fn tag(&self, tagid: &str) -> String {
+        if tagid.is_empty() {
+            return String::new();
+        }
+        self.mask(self.tagid, tagid).unwrap_or_default()
+    }
+
+    // session state as pushed on public events
+    pub fn public(&self, data_set: &AuthState) -> AuthState {
+        let mut public = data_set.clone();
+        public.tagid = self.tag(&data_set.tagid);
+        public.uid = self.tag(&data_set.uid);
+        public.parent_idtag = data_set.parent_idtag.as_ref().map(|idtag| self.tag(idtag));
+        public.name = match &data_set.name {
+            Some(name) => self.mask(self.name, name),
+            None => None,
+        };
+        public
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(name: PrivacyMask, tagid: PrivacyMask) -> PrivacyPolicy {
+        PrivacyPolicy {
+            name,
+            tagid,
+            salt: "charger-salt".to_string(),
+        }
+    }
+
+    #[test]
+    fn mask_name() {
+        let privacy = policy(PrivacyMask::Initials, PrivacyMask::Clear);
+        assert_eq!(
+            privacy.mask(PrivacyMask::Initials, "jean  pierre martin"),
+            Some("J.P.M.".to_string())
+        );
+        assert_eq!(privacy.mask(PrivacyMask::Hidden, "Jean Martin"), None);
+    }
+
+    #[test]
+    fn mask_tagid() {
+        let privacy = policy(PrivacyMask::Hidden, PrivacyMask::Truncated);
+        assert_eq!(privacy.tag("04A1B2C3"), "*B2C3");
+        assert_eq!(privacy.tag("C3"), "*C3");
+        assert_eq!(privacy.tag(""), "");
+    }
+
+    #[test]
+    fn hash_salted() {
+        let privacy = policy(PrivacyMask::Hidden, PrivacyMask::Hash);
+        let other = PrivacyPolicy {
+            salt: "other-salt".to_string(),
+            ..policy(PrivacyMask::Hidden, PrivacyMask::Hash)
+        };
+        let hash = privacy.tag("04A1B2C3");
+        assert_eq!(hash.len(), 16);
+        assert_eq!(hash, privacy.tag("04A1B2C3"));
+        assert_ne!(hash, privacy.tag("04A1B2C4"));
+        assert_ne!(hash, other.tag("04A1B2C3"));
+    }
+
+    #[test]
+    fn public_state() {
+        let privacy = policy(PrivacyMask::Initials, PrivacyMask::Truncated);
+        let mut data_set = AuthState::default();
+        data_set.tagid = "04A1B2C3".to_string();
+        data_set.uid = "DEADBEEF".to_string();
+        data_set.parent_idtag = Some("GROUP0042".to_string());
+        data_set.name = Some("Jean Martin".to_string());
+
+        let public = privacy.public(&data_set);
+        assert_eq!(public.tagid, "*B2C3");
+        assert_eq!(public.uid, "*BEEF");
+        assert_eq!(public.parent_idtag, Some("*0042".to_string()));
+        assert_eq!(public.name, Some("J.M.".to_string()));
+        // session state itself keeps clear values
+        assert_eq!(data_set.tagid, "04A1B2C3");
+    }
+}
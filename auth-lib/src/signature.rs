@@ -0,0 +1,388 @@
+/*
+ * Copyright (C) 2015-2022 IoT.bzh Company
+ * Author: Fulup Ar Foll <fulup@iot.bzh>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ */
+
+use afbv4::prelude::*;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use typesv4::prelude::*;
+
+const HMAC_KEY_MIN_LEN: usize = 16;
+
+enum ContractKeyValue {
+    Hmac(Vec<u8>),
+    Ed25519(VerifyingKey),
+}
+
+// contract signing key from binding config, hmac secret or ed25519 public key
+pub struct ContractKey {
+    uid: String,
+    value: ContractKeyValue,
+    signing: Option<SigningKey>, // ed25519 private seed, only on provisioning stations
+}
+
+impl ContractKey {
+    pub fn new(uid: &str, kind: &str, value: &str) -> Result<Self, AfbError> {
+        let bytes = match hex::decode(value) {
+            Ok(bytes) => bytes,
+            Err(error) => return afb_error!("contract-key-invalid", "key:{} {}", uid, error),
+        };
+
+        let value = match kind {
+            // a short shared secret is as good as no signature
+            "hmac-sha256" if bytes.len() < HMAC_KEY_MIN_LEN => {
+                return afb_error!(
+                    "contract-key-invalid",
+                    "key:{} hmac secret expect at least {} bytes",
+                    uid,
+                    HMAC_KEY_MIN_LEN
+                )
+            }
+            "hmac-sha256" => ContractKeyValue::Hmac(bytes),
+            "ed25519" => {
+                let key = match <[u8; 32]>::try_from(bytes.as_slice()) {
+                    Ok(key) => key,
+                    Err(_) => {
+                        return afb_error!("contract-key-invalid", "key:{} expect 32 bytes", uid)
+                    }
+                };
+                match VerifyingKey::from_bytes(&key) {
+                    Ok(key) => ContractKeyValue::Ed25519(key),
+                    Err(error) => {
+                        return afb_error!("contract-key-invalid", "key:{} {}", uid, error)
+                    }
+                }
+            }
+            _ => {
+                return afb_error!(
+                    "contract-key-invalid",
+                    "key:{} type:{} should be hmac-sha256|ed25519",
+                    uid,
+                    kind
+                )
+            }
+        };
+
+        Ok(ContractKey {
+            uid: uid.to_string(),
+            value,
+            signing: None,
+        })
+    }
+
+    // ed25519 private seed (hex), hmac keys sign with their shared secret
+    pub fn set_seed(mut self, seed: &str) -> Result<Self, AfbError> {
+        let seed = match hex::decode(seed)
+            .ok()
+            .and_then(|seed| <[u8; 32]>::try_from(seed.as_slice()).ok())
+        {
+            Some(seed) => seed,
+            None => {
+                return afb_error!(
+                    "contract-key-invalid",
+                    "key:{} seed expect 32 hex bytes",
+                    self.uid
+                )
+            }
+        };
+
+        let signing = SigningKey::from_bytes(&seed);
+        match &self.value {
+            ContractKeyValue::Ed25519(key) if *key == signing.verifying_key() => {}
+            _ => {
+                return afb_error!(
+                    "contract-key-invalid",
+                    "key:{} seed does not match ed25519 public key",
+                    self.uid
+                )
+            }
+        }
+        self.signing = Some(signing);
+        Ok(self)
+    }
+
+    fn sign(&self, payload: &[u8]) -> Option<Vec<u8>> {
+        match &self.value {
+            ContractKeyValue::Hmac(secret) => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).ok()?;
+                mac.update(payload);
+                Some(mac.finalize().into_bytes().to_vec())
+            }
+            ContractKeyValue::Ed25519(_) => self
+                .signing
+                .as_ref()
+                .map(|signing| signing.sign(payload).to_bytes().to_vec()),
+        }
+    }
+
+    fn verify(&self, payload: &[u8], signature: &[u8]) -> bool {
+        match &self.value {
+            ContractKeyValue::Hmac(secret) => match Hmac::<Sha256>::new_from_slice(secret) {
+                Ok(mut mac) => {
+                    mac.update(payload);
+                    mac.verify_slice(signature).is_ok()
+                }
+                Err(_) => false,
+            },
+            ContractKeyValue::Ed25519(key) => match Signature::from_slice(signature) {
+                Ok(signature) => key.verify(payload, &signature).is_ok(),
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+// contract signature check, disabled when no key is configured
+pub struct SignaturePolicy {
+    pub required: bool, // refuse unsigned contracts
+    pub keys: Vec<ContractKey>,
+    pub sign_key: Option<String>, // key used by provisioning verbs, default first key able to sign
+}
+
+impl SignaturePolicy {
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    // signature covers tagid, card uid and every contract field
+    pub fn verify(&self, data_set: &AuthState) -> Result<(), AfbError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let (contract, signature) = match &data_set.contract {
+            Some(contract) => match &contract.signature {
+                Some(signature) => (contract, signature),
+                None if self.required => {
+                    return afb_error!("contract-signature-invalid", "unsigned contract refused")
+                }
+                None => return Ok(()),
+            },
+            None if self.required => {
+                return afb_error!("contract-signature-invalid", "no contract to verify")
+            }
+            None => return Ok(()),
+        };
+
+        let signature = match hex::decode(signature) {
+            Ok(signature) => signature,
+            Err(error) => {
+                return afb_error!(
+                    "contract-signature-invalid",
+                    "malformed signature {}",
+                    error
+                )
+            }
+        };
+
+        let payload = contract.signed_payload(&data_set.tagid, &data_set.uid);
+        let verified = self
+            .keys
+            .iter()
+            .filter(|key| match &contract.kid {
+                Some(kid) => key.uid == *kid,
+                None => true,
+            })
+            .any(|key| key.verify(&payload, &signature));
+
+        if !verified {
+            return afb_error!(
+                "contract-signature-invalid",
+                "contract signature does not match badge kid:{:?}",
+                contract.kid
+            );
+        }
+        Ok(())
+    }
+
+    // sign contract for badge tagid and card uid
+    pub fn sign(&self, contract: &mut Contract, tagid: &str, uid: &str) -> Result<(), AfbError> {
+        contract.signature = None;
+        for key in &self.keys {
+            if let Some(sign_key) = &self.sign_key {
+                if key.uid != *sign_key {
+                    continue;
+                }
+            }
+            contract.kid = Some(key.uid.clone());
+            if let Some(signature) = key.sign(&contract.signed_payload(tagid, uid)) {
+                contract.signature = Some(hex::encode(signature));
+                return Ok(());
+            }
+        }
+        contract.kid = None;
+        afb_error!(
+            "contract-sign-fail",
+            "no signing key available (sign_key:{:?})",
+            self.sign_key
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HMAC_SECRET: &str = "000102030405060708090a0b0c0d0e0f";
+    const ED25519_SEED: [u8; 32] = [7; 32];
+
+    fn contract() -> Contract {
+        Contract {
+            version: 1,
+            imax: 16,
+            pmax: 11,
+            ocpp: false,
+            expiry_date: None,
+            issuer: None,
+            emax: 0,
+            windows: Vec::new(),
+            kid: None,
+            signature: None,
+        }
+    }
+
+    fn badge(contract: Contract) -> AuthState {
+        let mut data_set = AuthState::default();
+        data_set.tagid = "TAG-01".to_string();
+        data_set.uid = "04A2B3C4".to_string();
+        data_set.contract = Some(contract);
+        data_set
+    }
+
+    fn policy(keys: Vec<ContractKey>, sign_key: Option<&str>) -> SignaturePolicy {
+        SignaturePolicy {
+            required: true,
+            keys,
+            sign_key: sign_key.map(str::to_string),
+        }
+    }
+
+    fn hmac_key(uid: &str) -> ContractKey {
+        ContractKey::new(uid, "hmac-sha256", HMAC_SECRET).unwrap()
+    }
+
+    fn ed25519_key(uid: &str) -> ContractKey {
+        let public = SigningKey::from_bytes(&ED25519_SEED).verifying_key();
+        ContractKey::new(uid, "ed25519", &hex::encode(public.to_bytes())).unwrap()
+    }
+
+    // sign for TAG-01/04A2B3C4 and return the signed badge
+    fn signed(policy: &SignaturePolicy) -> AuthState {
+        let mut contract = contract();
+        policy.sign(&mut contract, "TAG-01", "04A2B3C4").unwrap();
+        badge(contract)
+    }
+
+    #[test]
+    fn hmac_sign_verify() {
+        let policy = policy(vec![hmac_key("k1")], None);
+        let data_set = signed(&policy);
+        let contract = data_set.contract.as_ref().unwrap();
+        assert_eq!(contract.kid.as_deref(), Some("k1"));
+        assert_eq!(contract.signature.as_ref().unwrap().len(), 64);
+        assert!(policy.verify(&data_set).is_ok());
+    }
+
+    #[test]
+    fn ed25519_sign_verify() {
+        let key = ed25519_key("k2")
+            .set_seed(&hex::encode(ED25519_SEED))
+            .unwrap();
+        let policy = policy(vec![key], None);
+        let data_set = signed(&policy);
+        assert_eq!(
+            data_set
+                .contract
+                .as_ref()
+                .unwrap()
+                .signature
+                .as_ref()
+                .unwrap()
+                .len(),
+            128
+        );
+        assert!(policy.verify(&data_set).is_ok());
+
+        // charger only holds the public key, it verifies but cannot sign
+        let verifier = SignaturePolicy {
+            required: true,
+            keys: vec![ed25519_key("k2")],
+            sign_key: None,
+        };
+        assert!(verifier.verify(&data_set).is_ok());
+        assert!(verifier
+            .sign(&mut contract(), "TAG-01", "04A2B3C4")
+            .is_err());
+    }
+
+    #[test]
+    fn tampered_contract() {
+        let policy = policy(vec![hmac_key("k1")], None);
+        let mut data_set = signed(&policy);
+        data_set.contract.as_mut().unwrap().imax = 32;
+        assert!(policy.verify(&data_set).is_err());
+    }
+
+    #[test]
+    fn cloned_card() {
+        let policy = policy(vec![hmac_key("k1")], None);
+        let mut data_set = signed(&policy);
+        data_set.uid = "04A2B3C5".to_string();
+        assert!(policy.verify(&data_set).is_err());
+
+        let mut data_set = signed(&policy);
+        data_set.tagid = "TAG-02".to_string();
+        assert!(policy.verify(&data_set).is_err());
+    }
+
+    #[test]
+    fn kid_selects_key() {
+        let other = ContractKey::new("k0", "hmac-sha256", "ffeeddccbbaa99887766554433221100");
+        let policy = policy(vec![other.unwrap(), hmac_key("k1")], Some("k1"));
+        let mut data_set = signed(&policy);
+        assert!(policy.verify(&data_set).is_ok());
+
+        data_set.contract.as_mut().unwrap().kid = Some("k0".to_string());
+        assert!(policy.verify(&data_set).is_err());
+    }
+
+    #[test]
+    fn unsigned_contract() {
+        let mut policy = policy(vec![hmac_key("k1")], None);
+        assert!(policy.verify(&badge(contract())).is_err());
+        let mut no_contract = badge(contract());
+        no_contract.contract = None;
+        assert!(policy.verify(&no_contract).is_err());
+
+        policy.required = false;
+        assert!(policy.verify(&badge(contract())).is_ok());
+        assert!(policy.verify(&no_contract).is_ok());
+    }
+
+    #[test]
+    fn disabled_without_keys() {
+        let policy = policy(Vec::new(), None);
+        assert!(!policy.is_enabled());
+        assert!(policy.verify(&badge(contract())).is_ok());
+    }
+
+    #[test]
+    fn invalid_keys() {
+        assert!(ContractKey::new("k1", "hmac-sha256", "").is_err());
+        assert!(ContractKey::new("k1", "hmac-sha256", "0001020304050607").is_err());
+        assert!(ContractKey::new("k1", "hmac-sha256", "not-hex").is_err());
+        assert!(ContractKey::new("k1", "ed25519", HMAC_SECRET).is_err());
+        assert!(ContractKey::new("k1", "rsa", HMAC_SECRET).is_err());
+        assert!(hmac_key("k1").set_seed(&hex::encode(ED25519_SEED)).is_err());
+        assert!(ed25519_key("k2").set_seed(&hex::encode([8; 32])).is_err());
+    }
+}
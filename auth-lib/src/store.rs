@@ -0,0 +1,39 @@
+/*
+ * Copyright (C) 2015-2022 IoT.bzh Company
+ * Author: Fulup Ar Foll <fulup@iot.bzh>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ */
+
+use std::fs;
+use std::io::{self, Write};
+
+// write a temporary file and rename it, a crash never leaves a truncated file behind
+pub(crate) fn write_atomic(path: &str, data: &str) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(data.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_replaces_file() {
+        let path = std::env::temp_dir().join(format!("auth-store-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_atomic(path, "first").unwrap();
+        write_atomic(path, "second").unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "second");
+        assert!(fs::metadata(format!("{}.tmp", path)).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
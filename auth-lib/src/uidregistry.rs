@@ -0,0 +1,173 @@
+/*
+ * Copyright (C) 2015-2022 IoT.bzh Company
+ * Author: Fulup Ar Foll <fulup@iot.bzh>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ */
+
+use crate::store::write_atomic;
+use afbv4::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use typesv4::prelude::*;
+
+// issued badges registry, binds tagid to card hardware uid to detect badge data copied
+// onto another card and keeps track of revoked badges
+pub struct UidRegistry {
+    entries: HashMap<String, AuthBadge>,
+    path: Option<&'static str>,
+    required: bool, // refuse tags without binding unless their contract is signed
+}
+
+impl UidRegistry {
+    pub fn new(path: Option<&'static str>, required: bool) -> Result<Self, AfbError> {
+        let mut registry = UidRegistry {
+            entries: HashMap::new(),
+            path,
+            required,
+        };
+
+        // registry file is a json array of AuthBadge records
+        if let Some(path) = path {
+            match fs::read_to_string(path) {
+                Ok(data) => match serde_json::from_str::<Vec<AuthBadge>>(&data) {
+                    Ok(badges) => {
+                        for badge in badges {
+                            registry.entries.insert(badge.tagid.clone(), badge);
+                        }
+                    }
+                    Err(error) => {
+                        return afb_error!(
+                            "uid-registry-load",
+                            "invalid uid registry file:{} error:{}",
+                            path,
+                            error
+                        )
+                    }
+                },
+                Err(error) => {
+                    afb_log_msg!(
+                        Notice,
+                        None,
+                        "uid registry file:{} not loaded ({})",
+                        path,
+                        error
+                    );
+                }
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn get(&self, tagid: &str) -> Option<&AuthBadge> {
+        self.entries.get(tagid)
+    }
+
+    // signed contracts carry their own tagid/uid binding, errors do not carry
+    // identifiers as they end up in logs and public security events
+    pub fn check(&self, tagid: &str, uid: &str, signed: bool) -> Result<(), AfbError> {
+        match self.entries.get(tagid) {
+            Some(badge) if badge.revoked => afb_error!("uid-binding-revoked", "badge revoked"),
+            Some(badge) if badge.uid.eq_ignore_ascii_case(uid) => Ok(()),
+            Some(_) => afb_error!(
+                "uid-binding-mismatch",
+                "badge presented on a card it is not bound to"
+            ),
+            None if signed || !self.required => Ok(()),
+            None => afb_error!("uid-binding-unknown", "badge has no uid binding"),
+        }
+    }
+
+    // record issued/updated badge and persist registry
+    pub fn update(&mut self, badge: AuthBadge) -> Result<(), AfbError> {
+        self.entries.insert(badge.tagid.clone(), badge);
+        self.store()
+    }
+
+    pub fn revoke(&mut self, tagid: &str) -> Result<AuthBadge, AfbError> {
+        let badge = match self.entries.get_mut(tagid) {
+            Some(badge) => {
+                badge.revoked = true;
+                badge.clone()
+            }
+            None => return afb_error!("uid-registry-revoke", "tagid not registered"),
+        };
+        self.store()?;
+        Ok(badge)
+    }
+
+    fn store(&self) -> Result<(), AfbError> {
+        let path = match self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let badges: Vec<&AuthBadge> = self.entries.values().collect();
+        let data = match serde_json::to_string(&badges) {
+            Ok(value) => value,
+            Err(error) => return afb_error!("uid-registry-store", "serialize error:{}", error),
+        };
+
+        if let Err(error) = write_atomic(path, &data) {
+            return afb_error!(
+                "uid-registry-store",
+                "fail to write file:{} error:{}",
+                path,
+                error
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn badge(tagid: &str, uid: &str) -> AuthBadge {
+        AuthBadge {
+            tagid: tagid.to_string(),
+            uid: uid.to_string(),
+            name: None,
+            issued: Duration::from_secs(0),
+            revoked: false,
+        }
+    }
+
+    #[test]
+    fn bound_card() {
+        let mut registry = UidRegistry::new(None, true).unwrap();
+        registry.update(badge("TAG-01", "04A2B3C4")).unwrap();
+        assert!(registry.check("TAG-01", "04A2B3C4", false).is_ok());
+        assert!(registry.check("TAG-01", "04a2b3c4", false).is_ok());
+        // tag data copied onto another card, even with a signed contract
+        assert!(registry.check("TAG-01", "04A2B3C5", false).is_err());
+        assert!(registry.check("TAG-01", "04A2B3C5", true).is_err());
+    }
+
+    #[test]
+    fn unknown_tag() {
+        let required = UidRegistry::new(None, true).unwrap();
+        assert!(required.check("TAG-02", "04A2B3C4", false).is_err());
+        assert!(required.check("TAG-02", "04A2B3C4", true).is_ok());
+
+        let optional = UidRegistry::new(None, false).unwrap();
+        assert!(optional.check("TAG-02", "04A2B3C4", false).is_ok());
+    }
+
+    #[test]
+    fn revoked_badge() {
+        let mut registry = UidRegistry::new(None, false).unwrap();
+        registry.update(badge("TAG-01", "04A2B3C4")).unwrap();
+        assert!(registry.revoke("TAG-01").unwrap().revoked);
+        assert!(registry.get("TAG-01").unwrap().revoked);
+        assert!(registry.check("TAG-01", "04A2B3C4", true).is_err());
+        assert!(registry.revoke("TAG-02").is_err());
+    }
+}